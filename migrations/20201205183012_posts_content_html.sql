@@ -0,0 +1,2 @@
+-- cache of the rendered markdown, refreshed every time the post content is updated
+alter table posts add column if not exists content_html text not null default '';
@@ -0,0 +1,6 @@
+alter table posts add column if not exists status smallint not null default 1;
+alter table posts add column if not exists published_at timestamp null;
+-- everything written before posts had a status was live
+update posts set status = 3, published_at = created_at where published_at is null;
+create index on posts(status, published_at);
+comment on column posts.status is '1 - Draft, 2 - Pending Review, 3 - Published, 4 - Scheduled, 5 - Private';
@@ -0,0 +1,13 @@
+create table if not exists post_reviews
+(
+    id              uuid        primary key default uuid_generate_v4(),
+    post_id         uuid        not null,
+    user_id         uuid        not null,
+    action          smallint    not null,
+    note            text        null,
+    created_at      timestamp   not null default now(),
+    foreign key (post_id) references posts(id) on delete cascade,
+    foreign key (user_id) references users(id)
+);
+create index on post_reviews(post_id, created_at);
+comment on column post_reviews.action is '1 - Submitted, 2 - Approved, 3 - Changes Requested, 4 - Rejected';
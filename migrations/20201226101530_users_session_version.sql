@@ -0,0 +1,2 @@
+-- stored in the identity cookie, sessions with an older version are rejected
+alter table users add column if not exists session_version integer not null default 1;
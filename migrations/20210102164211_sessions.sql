@@ -0,0 +1,11 @@
+create table if not exists sessions
+(
+    id                  uuid        primary key default uuid_generate_v4(),
+    user_id             uuid        not null,
+    user_agent          text        null constraint user_agent_length check ( char_length(user_agent) <= 512 ),
+    ip                  text        null constraint ip_length check ( char_length(ip) <= 64 ),
+    created_at          timestamp   not null default now(),
+    last_seen_at        timestamp   not null default now(),
+    foreign key (user_id) references users(id) on delete cascade
+);
+create index on sessions(user_id, last_seen_at);
@@ -0,0 +1,15 @@
+-- accounts at external identity providers, a user can have one per provider
+create table if not exists user_identities
+(
+    id                  uuid        primary key default uuid_generate_v4(),
+    user_id             uuid        not null,
+    provider            text        not null constraint provider_length check ( char_length(provider) <= 64 ),
+    subject             text        not null constraint subject_length check ( char_length(subject) <= 255 ),
+    created_at          timestamp   not null default now(),
+    unique (provider, subject),
+    foreign key (user_id) references users(id) on delete cascade
+);
+create index on user_identities(user_id);
+insert into user_identities (user_id, provider, subject)
+    select id, 'github', github_id::text from users where github_id is not null
+on conflict do nothing;
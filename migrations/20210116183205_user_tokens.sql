@@ -0,0 +1,18 @@
+-- set once the user clicked the link in the verification email, cleared when the email changes
+alter table users add column if not exists email_verified boolean not null default false;
+
+-- single use tokens sent by email, only the sha-256 hash of the token is stored
+create table if not exists user_tokens
+(
+    id                  uuid        primary key default uuid_generate_v4(),
+    user_id             uuid        not null,
+    purpose             smallint    not null,
+    token_hash          text        not null unique,
+    email               text        null constraint email_length check ( char_length(email) <= 255 ),
+    expires_at          timestamp   not null,
+    used_at             timestamp   null,
+    created_at          timestamp   not null default now(),
+    foreign key (user_id) references users(id) on delete cascade
+);
+create index on user_tokens(user_id, purpose);
+comment on column user_tokens.purpose is '1 - Password reset, 2 - Email verification';
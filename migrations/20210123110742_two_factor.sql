@@ -0,0 +1,22 @@
+-- TOTP two-factor authentication, enabled when a user has a row
+create table if not exists two_factor
+(
+    user_id             uuid        primary key,
+    secret              text        not null,
+    -- the time step of the last accepted code, so a code can't be used twice
+    last_step           bigint      null,
+    created_at          timestamp   not null default now(),
+    foreign key (user_id) references users(id) on delete cascade
+);
+
+-- one time codes to sign in without the authenticator, only the sha-256 hash is stored
+create table if not exists recovery_codes
+(
+    id                  uuid        primary key default uuid_generate_v4(),
+    user_id             uuid        not null,
+    code_hash           text        not null,
+    used_at             timestamp   null,
+    created_at          timestamp   not null default now(),
+    unique (user_id, code_hash),
+    foreign key (user_id) references users(id) on delete cascade
+);
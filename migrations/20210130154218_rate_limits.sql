@@ -0,0 +1,9 @@
+-- failed logins and other limited attempts, only used with RATE_LIMIT_STORE=postgres so
+-- several instances share the counts
+create table if not exists rate_limits
+(
+    key                 text        primary key,
+    failures            integer     not null,
+    last_failure_at     timestamp   not null default now()
+);
+create index on rate_limits(last_failure_at);
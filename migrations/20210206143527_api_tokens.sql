@@ -0,0 +1,15 @@
+-- personal tokens for scripts using the json api, only the sha-256 hash of the token is stored
+create table if not exists api_tokens
+(
+    id                  uuid        primary key default uuid_generate_v4(),
+    user_id             uuid        not null,
+    name                text        not null constraint name_length check ( char_length(name) <= 100 ),
+    token_hash          text        not null unique,
+    scopes              text[]      not null,
+    expires_at          timestamp   null,
+    last_used_at        timestamp   null,
+    created_at          timestamp   not null default now(),
+    foreign key (user_id) references users(id) on delete cascade
+);
+create index on api_tokens(user_id);
+comment on column api_tokens.scopes is 'e.g. posts:write, see Scope';
@@ -0,0 +1,12 @@
+-- a single row once the first super admin was set up, the setup page never opens again then
+create table if not exists setup
+(
+    id                  boolean     primary key default true constraint single_row check ( id ),
+    user_id             uuid        null,
+    completed_at        timestamp   not null default now(),
+    foreign key (user_id) references users(id) on delete set null
+);
+-- installs that got their super admin with an sql update don't need the setup
+insert into setup (user_id)
+select id from users where role = 1 order by created_at limit 1
+on conflict do nothing;
@@ -0,0 +1,12 @@
+-- set when a session is started, shown to admins
+alter table users add column if not exists last_login_at timestamp null;
+
+-- suspended users can't sign in, their sessions are deleted when they're suspended
+create table if not exists suspensions
+(
+    user_id             uuid        primary key,
+    suspended_by        uuid        null,
+    created_at          timestamp   not null default now(),
+    foreign key (user_id) references users(id) on delete cascade,
+    foreign key (suspended_by) references users(id) on delete set null
+);
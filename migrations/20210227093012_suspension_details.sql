@@ -0,0 +1,5 @@
+-- suspensions without an end date last until the user is unsuspended
+alter table suspensions add column if not exists until timestamp null;
+-- shown to the suspended user
+alter table suspensions add column if not exists reason text null
+    constraint reason_length check ( char_length(reason) <= 500 );
@@ -0,0 +1,15 @@
+-- what authors tell about themselves on their public page, `/author/{username}`
+create table if not exists user_profiles
+(
+    user_id             uuid        primary key,
+    bio                 text        null constraint bio_length check ( char_length(bio) <= 1000 ),
+    website             text        null constraint website_length check ( char_length(website) <= 255 ),
+    twitter             text        null constraint twitter_length check ( char_length(twitter) <= 15 ),
+    github              text        null constraint github_length check ( char_length(github) <= 39 ),
+    mastodon            text        null constraint mastodon_length check ( char_length(mastodon) <= 255 ),
+    updated_at          timestamp   not null default now(),
+    foreign key (user_id) references users(id) on delete cascade
+);
+
+-- the archive of each author
+create index on posts(user_id, published_at);
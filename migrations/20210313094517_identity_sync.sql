@@ -0,0 +1,7 @@
+-- what the provider sent at the last login, user fields that still have these values were never
+-- changed locally and are kept in sync with the provider
+alter table user_identities add column if not exists username text null;
+alter table user_identities add column if not exists name text null;
+alter table user_identities add column if not exists email text null;
+alter table user_identities add column if not exists avatar_url text null;
+alter table user_identities add column if not exists synced_at timestamp null;
@@ -0,0 +1,32 @@
+use actix_web::dev::ConnectionInfo;
+use std::net::SocketAddr;
+
+/// Whether the ip address of clients is taken from the `Forwarded` or `X-Forwarded-For` headers,
+/// set with `TRUST_PROXY`. Only behind a reverse proxy that sets them, otherwise anyone can make
+/// up a new address.
+fn trust_proxy() -> bool {
+    dotenv::var("TRUST_PROXY")
+        .map(|v| v == "true")
+        .unwrap_or(false)
+}
+
+/// The ip address of the client, for the rate limits and the list of sessions. Takes the parts
+/// of the request both `HttpRequest` and `ServiceRequest` have.
+pub fn client_ip(
+    connection_info: &ConnectionInfo,
+    peer_addr: Option<SocketAddr>,
+) -> Option<String> {
+    let ip = if trust_proxy() {
+        connection_info
+            .realip_remote_addr()
+            .map(|ip| ip.to_string())
+    } else {
+        peer_addr.map(|addr| addr.ip().to_string())
+    }?;
+    // without a forwarded address it's the peer address, with its port
+    Some(
+        ip.parse::<SocketAddr>()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or(ip),
+    )
+}
@@ -0,0 +1,49 @@
+use crate::token;
+use actix_session::{Session, UserSession};
+use actix_web::dev::Payload;
+use actix_web::{Error, FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+
+/// Session key of the token, it lives as long as the session cookie.
+const CSRF_TOKEN_KEY: &str = "csrf_token";
+
+/// Name of the form field with the token, see `components/csrf_field`.
+pub const CSRF_FIELD: &str = "csrf_token";
+
+/// A random token kept in the session and rendered into every form. Other sites can make a
+/// browser post a form along with its cookies, but they can't read the token, so form posts
+/// without it are turned away, see `middleware::Csrf`.
+#[derive(Serialize)]
+#[serde(transparent)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// The token of the session, a new one for sessions without one.
+    pub fn get(session: &Session) -> Result<CsrfToken, Error> {
+        if let Some(token) = session.get::<String>(CSRF_TOKEN_KEY)? {
+            return Ok(CsrfToken(token));
+        }
+        let token = token::generate();
+        session.set(CSRF_TOKEN_KEY, &token)?;
+        Ok(CsrfToken(token))
+    }
+
+    /// Whether a posted token is the one of the session. The hashes are compared, so the time
+    /// it takes doesn't tell how much of the token was right.
+    pub fn matches(session: &Session, posted: &str) -> bool {
+        match session.get::<String>(CSRF_TOKEN_KEY) {
+            Ok(Some(token)) => token::hash(&token) == token::hash(posted),
+            _ => false,
+        }
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = Error;
+    type Future = Ready<Result<CsrfToken, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(CsrfToken::get(&req.get_session()))
+    }
+}
@@ -0,0 +1,282 @@
+use crate::csrf::CsrfToken;
+use crate::database::DbPool;
+use crate::handlers::email_handlers::send_verification_email;
+use crate::mailer::Mailer;
+use crate::models::user::{is_valid_username, Role};
+use crate::models::{Session, User, UserRequest};
+use crate::password;
+use crate::session::{self, safe_return_to, LoggedUser, SessionCache};
+use actix_identity::Identity;
+use actix_web::http::{header, StatusCode};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use handlebars::Handlebars;
+use serde_json::json;
+use std::time::SystemTime;
+use time::PrimitiveDateTime;
+
+/// Whether anyone can create an account with a password, set with `ALLOW_REGISTRATION`.
+pub fn registration_open() -> bool {
+    dotenv::var("ALLOW_REGISTRATION")
+        .map(|v| v == "true")
+        .unwrap_or(false)
+}
+
+#[derive(Deserialize)]
+pub struct RegisterQuery {
+    return_to: Option<String>,
+}
+
+#[get("/register")]
+async fn register_page(
+    logged_user: Option<LoggedUser>,
+    csrf_token: CsrfToken,
+    hb: web::Data<Handlebars<'_>>,
+    params: web::Query<RegisterQuery>,
+) -> HttpResponse {
+    let return_to = safe_return_to(params.return_to.as_deref());
+    if logged_user.is_some() {
+        return HttpResponse::Found()
+            .header(header::LOCATION, return_to)
+            .finish();
+    }
+    if !registration_open() {
+        return HttpResponse::NotFound().finish();
+    }
+    render_register(&hb, &csrf_token, &return_to, "", "", None, StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    username: String,
+    email: String,
+    password: String,
+    password_confirmation: String,
+    return_to: Option<String>,
+}
+
+/// Creates a subscriber with a password and signs them in.
+#[post("/register")]
+async fn register(
+    req: HttpRequest,
+    id: Identity,
+    csrf_token: CsrfToken,
+    form: web::Form<RegisterRequest>,
+    hb: web::Data<Handlebars<'_>>,
+    mailer: web::Data<Mailer>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    if !registration_open() {
+        return HttpResponse::NotFound().finish();
+    }
+    let form = form.into_inner();
+    let return_to = safe_return_to(form.return_to.as_deref());
+    let username = form.username.trim();
+    let email = form.email.trim();
+    let error = |message| {
+        render_register(
+            &hb,
+            &csrf_token,
+            &return_to,
+            username,
+            email,
+            Some(message),
+            StatusCode::BAD_REQUEST,
+        )
+    };
+
+    if !is_valid_username(username) {
+        return error("Usernames can only contain letters, numbers and single dashes.");
+    }
+    if User::find_by_username(username, db_pool.get_ref())
+        .await
+        .is_ok()
+    {
+        return error("This username is taken.");
+    }
+    if !email.is_empty() && (!email.contains('@') || email.len() > 255) {
+        return error("This email address is not valid.");
+    }
+    if form.password != form.password_confirmation {
+        return error("The passwords don't match.");
+    }
+    if let Err(e) = password::check_strength(&form.password, username) {
+        return error(e);
+    }
+
+    let hash = match password::hash(form.password).await {
+        Ok(h) => h,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let user = UserRequest {
+        username: username.to_string(),
+        email: if email.is_empty() {
+            None
+        } else {
+            Some(email.to_string())
+        },
+        password: Some(hash),
+        name: None,
+        avatar_url: None,
+        gravatar_id: None,
+        github_id: None,
+        github_token: None,
+        role: Role::Subscriber,
+        created_at: PrimitiveDateTime::from(SystemTime::now()),
+        updated_at: PrimitiveDateTime::from(SystemTime::now()),
+    };
+    let user = match User::create(user, db_pool.get_ref()).await {
+        Ok(u) => u,
+        // someone else took the username in the meantime
+        Err(_) => return error("This username is taken."),
+    };
+    if let Err(e) = send_verification_email(&user, &hb, &mailer, db_pool.get_ref()).await {
+        log::error!("Failed to send verification email: {}", e);
+    }
+
+    if session::login(&req, &id, &user, db_pool.get_ref())
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Found()
+        .header(header::LOCATION, return_to)
+        .finish()
+}
+
+fn render_register(
+    hb: &Handlebars,
+    csrf_token: &CsrfToken,
+    return_to: &str,
+    username: &str,
+    email: &str,
+    error: Option<&str>,
+    status: StatusCode,
+) -> HttpResponse {
+    let data = json!({
+        "user": null,
+        "return_to": return_to,
+        "username": username,
+        "email": email,
+        "error": error,
+        "csrf_token": csrf_token,
+    });
+    let body = hb.render("register", &data).unwrap();
+
+    HttpResponse::build(status).body(body)
+}
+
+#[get("/account/password")]
+async fn password_page(
+    logged_user: LoggedUser,
+    csrf_token: CsrfToken,
+    hb: web::Data<Handlebars<'_>>,
+) -> HttpResponse {
+    render_password(&hb, &logged_user, &csrf_token, None, None, StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+pub struct PasswordChange {
+    /// Not needed when the user doesn't have a password yet, e.g. they signed up with GitHub.
+    current_password: Option<String>,
+    password: String,
+    password_confirmation: String,
+}
+
+/// Sets or changes the password of the logged in user, which signs them out everywhere else.
+#[post("/account/password")]
+async fn change_password(
+    req: HttpRequest,
+    id: Identity,
+    logged_user: LoggedUser,
+    csrf_token: CsrfToken,
+    form: web::Form<PasswordChange>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    let error = |message| {
+        render_password(
+            &hb,
+            &logged_user,
+            &csrf_token,
+            Some(message),
+            None,
+            StatusCode::BAD_REQUEST,
+        )
+    };
+
+    if logged_user.password.is_some()
+        && !password::verify(
+            logged_user.password.clone(),
+            form.current_password.unwrap_or_default(),
+        )
+        .await
+    {
+        return error("The current password is wrong.");
+    }
+    if form.password != form.password_confirmation {
+        return error("The passwords don't match.");
+    }
+    if let Err(e) = password::check_strength(&form.password, &logged_user.username) {
+        return error(e);
+    }
+
+    let hash = match password::hash(form.password).await {
+        Ok(h) => h,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let db_pool = db_pool.get_ref();
+    let result = async {
+        User::set_password(logged_user.id, &hash, db_pool).await?;
+        Session::delete_by_user_id(logged_user.id, db_pool).await?;
+        User::bump_session_version(logged_user.id, db_pool).await?;
+        cache.remove(logged_user.id);
+        cache.remove_user_sessions(logged_user.id);
+        // keep this browser signed in with a new session
+        let user = User::find_by_id(logged_user.id, db_pool).await?;
+        session::login(&req, &id, &user, db_pool).await?;
+        Ok::<User, anyhow::Error>(user)
+    }
+    .await;
+
+    match result {
+        Ok(user) => render_password(
+            &hb,
+            &user,
+            &csrf_token,
+            None,
+            Some("Your password has been saved."),
+            StatusCode::OK,
+        ),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+fn render_password(
+    hb: &Handlebars,
+    user: &User,
+    csrf_token: &CsrfToken,
+    error: Option<&str>,
+    success: Option<&str>,
+    status: StatusCode,
+) -> HttpResponse {
+    let data = json!({
+        "user": user,
+        "has_password": user.password.is_some(),
+        "error": error,
+        "success": success,
+        "csrf_token": csrf_token,
+    });
+    let body = hb.render("password", &data).unwrap();
+
+    HttpResponse::build(status).body(body)
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(register_page);
+    cfg.service(register);
+    cfg.service(password_page);
+    cfg.service(change_password);
+}
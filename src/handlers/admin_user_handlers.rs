@@ -0,0 +1,574 @@
+use crate::csrf::CsrfToken;
+use crate::database::DbPool;
+use crate::middleware::{roles, RequireRole};
+use crate::models::suspension::MAX_REASON_LENGTH;
+use crate::models::user::Role;
+use crate::models::{Session, Suspension, User, UserDeletion, UserOverview};
+use crate::session::{LoggedUser, SessionCache};
+use actix_web::http::{header, StatusCode};
+use actix_web::{get, post, web, HttpResponse};
+use handlebars::Handlebars;
+use serde_json::json;
+use sqlx::types::Uuid;
+use url::form_urlencoded;
+
+const USERS_PER_PAGE: i64 = 25;
+
+/// Every role in the order they're offered in, the most privileged first.
+const ROLES: [Role; 6] = [
+    Role::SuperAdmin,
+    Role::Admin,
+    Role::Editor,
+    Role::Author,
+    Role::Contributor,
+    Role::Subscriber,
+];
+
+/// The search and page of the list, the actions send the admin back to them.
+#[derive(Deserialize)]
+pub struct ListQuery {
+    q: Option<String>,
+    page: Option<i64>,
+}
+
+impl ListQuery {
+    fn search(&self) -> &str {
+        self.q.as_deref().unwrap_or("").trim()
+    }
+
+    fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    // The query string of the list at `page`, without the leading `?`.
+    fn to_query(&self, page: i64) -> String {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        if !self.search().is_empty() {
+            query.append_pair("q", self.search());
+        }
+        query.append_pair("page", &page.to_string()).finish()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RoleForm {
+    role: Role,
+}
+
+/// Users searchable by username and email, with the actions the admin may take on each.
+#[get("/admin/users")]
+async fn list(
+    logged_user: RequireRole<roles::Admin>,
+    csrf_token: CsrfToken,
+    query: web::Query<ListQuery>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    render_users(
+        &hb,
+        &logged_user,
+        &csrf_token,
+        &query,
+        db_pool.get_ref(),
+        None,
+        None,
+    )
+    .await
+}
+
+#[post("/admin/users/{uuid}/role")]
+async fn change_role(
+    uuid: web::Path<String>,
+    logged_user: RequireRole<roles::Admin>,
+    csrf_token: CsrfToken,
+    query: web::Query<ListQuery>,
+    form: web::Form<RoleForm>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let user = match find_other_user(&uuid, &logged_user, db_pool).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+    // an admin can neither touch a super admin nor make someone one
+    if !logged_user.role.can_manage(user.role) || !logged_user.role.can_assign(form.role) {
+        let error = "You can't give this role to this user.";
+        return render_users(
+            &hb,
+            &logged_user,
+            &csrf_token,
+            &query,
+            db_pool,
+            Some(error),
+            None,
+        )
+        .await;
+    }
+
+    let result = match User::set_role(user.id, form.role, db_pool).await {
+        Ok(_) => User::require_two_factor_setup(user.id, form.role, db_pool).await,
+        Err(e) => Err(e),
+    };
+    cache.remove(user.id);
+    match result {
+        Ok(_) => {
+            log::info!(
+                "{} changed the role of {} to {:?}",
+                logged_user.username,
+                user.username,
+                form.role
+            );
+            redirect(&query)
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Asks for the reason and the end of the suspension.
+#[get("/admin/users/{uuid}/suspend")]
+async fn suspend_page(
+    uuid: web::Path<String>,
+    logged_user: RequireRole<roles::Admin>,
+    csrf_token: CsrfToken,
+    query: web::Query<ListQuery>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let user = match find_other_user(&uuid, &logged_user, db_pool.get_ref()).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+    if !logged_user.role.can_manage(user.role) {
+        return HttpResponse::Forbidden().body("Forbidden");
+    }
+    render_suspend(
+        &hb,
+        &logged_user,
+        &csrf_token,
+        &user,
+        &query,
+        None,
+        StatusCode::OK,
+    )
+}
+
+#[derive(Deserialize)]
+pub struct SuspendForm {
+    /// Empty for no reason.
+    reason: String,
+    /// `YYYY-MM-DD`, empty for a suspension that lasts until the user is unsuspended.
+    until: String,
+}
+
+/// Signs the user out everywhere and keeps them from signing in until the suspension ends or
+/// they're unsuspended.
+#[post("/admin/users/{uuid}/suspend")]
+async fn suspend(
+    uuid: web::Path<String>,
+    logged_user: RequireRole<roles::Admin>,
+    csrf_token: CsrfToken,
+    query: web::Query<ListQuery>,
+    form: web::Form<SuspendForm>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let user = match find_other_user(&uuid, &logged_user, db_pool).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+    if !logged_user.role.can_manage(user.role) {
+        return HttpResponse::Forbidden().body("Forbidden");
+    }
+
+    let reason = form.reason.trim();
+    let today = time::OffsetDateTime::now_utc().date();
+    // the suspension ends when the chosen day starts
+    let until = match form.until.trim() {
+        "" => Ok(None),
+        until => match time::Date::parse(until, "%F") {
+            Ok(date) if date > today => Ok(Some(date.midnight())),
+            Ok(_) => Err("The suspension has to end after today."),
+            Err(_) => Err("The end of the suspension is not a valid date."),
+        },
+    };
+    let error = if reason.chars().count() > MAX_REASON_LENGTH {
+        Some("The reason can't be longer than 500 characters.")
+    } else {
+        until.err()
+    };
+    if error.is_some() {
+        return render_suspend(
+            &hb,
+            &logged_user,
+            &csrf_token,
+            &user,
+            &query,
+            error,
+            StatusCode::BAD_REQUEST,
+        );
+    }
+    let until = until.unwrap_or_default();
+    let reason = if reason.is_empty() {
+        None
+    } else {
+        Some(reason)
+    };
+
+    let result = match Suspension::create(user.id, logged_user.id, until, reason, db_pool).await {
+        Ok(_) => match Session::delete_by_user_id(user.id, db_pool).await {
+            // also ends logins that are waiting for their second factor
+            Ok(_) => User::bump_session_version(user.id, db_pool).await,
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    cache.remove_user_sessions(user.id);
+    match result {
+        Ok(_) => {
+            log::info!("{} suspended {}", logged_user.username, user.username);
+            redirect(&query)
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[post("/admin/users/{uuid}/unsuspend")]
+async fn unsuspend(
+    uuid: web::Path<String>,
+    logged_user: RequireRole<roles::Admin>,
+    csrf_token: CsrfToken,
+    query: web::Query<ListQuery>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let user = match find_other_user(&uuid, &logged_user, db_pool).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+    if !logged_user.role.can_manage(user.role) {
+        let error = "You can't unsuspend this user.";
+        return render_users(
+            &hb,
+            &logged_user,
+            &csrf_token,
+            &query,
+            db_pool,
+            Some(error),
+            None,
+        )
+        .await;
+    }
+
+    match Suspension::delete(user.id, db_pool).await {
+        Ok(_) => {
+            log::info!("{} unsuspended {}", logged_user.username, user.username);
+            redirect(&query)
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Asks what to do with the posts and reviews of the user before deleting them.
+#[get("/admin/users/{uuid}/delete")]
+async fn delete_page(
+    uuid: web::Path<String>,
+    logged_user: RequireRole<roles::Admin>,
+    csrf_token: CsrfToken,
+    query: web::Query<ListQuery>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let user = match find_other_user(&uuid, &logged_user, db_pool).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+    if !logged_user.role.can_manage(user.role) {
+        return HttpResponse::Forbidden().body("Forbidden");
+    }
+    render_delete(&hb, &logged_user, &csrf_token, &user, &query, db_pool, None).await
+}
+
+#[derive(Deserialize)]
+pub struct DeleteForm {
+    /// `delete`, `reassign` or `anonymize`.
+    action: String,
+    /// The username of the new author when reassigning.
+    reassign_to: Option<String>,
+}
+
+/// Deletes the user, giving their posts to another user, or anonymizes them and keeps the
+/// posts. Then lists the users again with what happened.
+#[post("/admin/users/{uuid}/delete")]
+async fn delete(
+    uuid: web::Path<String>,
+    logged_user: RequireRole<roles::Admin>,
+    csrf_token: CsrfToken,
+    query: web::Query<ListQuery>,
+    form: web::Form<DeleteForm>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let user = match find_other_user(&uuid, &logged_user, db_pool).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+    if !logged_user.role.can_manage(user.role) {
+        return HttpResponse::Forbidden().body("Forbidden");
+    }
+
+    let deletion = match form.action.as_str() {
+        "delete" => Ok(UserDeletion::Delete),
+        "anonymize" => Ok(UserDeletion::Anonymize),
+        "reassign" => {
+            let username = form.reassign_to.as_deref().unwrap_or("").trim();
+            let username = username.trim_start_matches('@');
+            match User::find_by_username(username, db_pool).await {
+                Ok(to) => UserDeletion::reassign_to(&user, to),
+                Err(_) => Err("There's no user with this username."),
+            }
+        }
+        _ => return HttpResponse::BadRequest().body("Invalid action"),
+    };
+    let deletion = match deletion {
+        Ok(d) => d,
+        Err(error) => {
+            return render_delete(
+                &hb,
+                &logged_user,
+                &csrf_token,
+                &user,
+                &query,
+                db_pool,
+                Some(error),
+            )
+            .await
+        }
+    };
+
+    match deletion.run(&user, db_pool).await {
+        Ok(outcome) => {
+            cache.remove_user_sessions(user.id);
+            let message = outcome.message(&user.username);
+            log::info!("{}: {}", logged_user.username, message);
+            render_users(
+                &hb,
+                &logged_user,
+                &csrf_token,
+                &query,
+                db_pool,
+                None,
+                Some(&message),
+            )
+            .await
+        }
+        // the user wrote posts or reviews since the page was shown
+        Err(_) => {
+            let mut response = render_delete(
+                &hb,
+                &logged_user,
+                &csrf_token,
+                &user,
+                &query,
+                db_pool,
+                Some("The user could not be deleted, choose what happens to their posts and reviews."),
+            )
+            .await;
+            *response.status_mut() = StatusCode::CONFLICT;
+            response
+        }
+    }
+}
+
+// The user to act on, admins can't change, suspend or delete themselves.
+async fn find_other_user(
+    uuid: &str,
+    logged_user: &LoggedUser,
+    db_pool: &DbPool,
+) -> Result<User, HttpResponse> {
+    let uuid_;
+    match Uuid::parse_str(uuid) {
+        Ok(u) => uuid_ = u,
+        Err(_) => return Err(HttpResponse::BadRequest().body("Invalid User ID")),
+    }
+    if uuid_ == logged_user.id {
+        return Err(HttpResponse::BadRequest().body("You can't change your own account here"));
+    }
+    User::find_by_id(uuid_, db_pool)
+        .await
+        .map_err(|_| HttpResponse::BadRequest().body("User not found"))
+}
+
+// Back to the list the action was taken on.
+fn redirect(query: &ListQuery) -> HttpResponse {
+    HttpResponse::Found()
+        .header(
+            header::LOCATION,
+            format!("/admin/users?{}", query.to_query(query.page())),
+        )
+        .finish()
+}
+
+// The page of users with an error after a failed action, or what a deletion did.
+async fn render_users(
+    hb: &Handlebars<'_>,
+    logged_user: &LoggedUser,
+    csrf_token: &CsrfToken,
+    query: &ListQuery,
+    db_pool: &DbPool,
+    error: Option<&str>,
+    notice: Option<&str>,
+) -> HttpResponse {
+    let page = query.page();
+    let (users, total) =
+        match UserOverview::search(query.search(), page, USERS_PER_PAGE, db_pool).await {
+            Ok(found) => found,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    let users: Vec<_> = users
+        .iter()
+        .map(|user| {
+            let mut providers = user.providers.clone();
+            // GitHub users used to be linked by `github_id` only
+            if user.github_id.is_some() && !providers.iter().any(|p| p == "github") {
+                providers.push("github".to_string());
+            }
+            if user.has_password {
+                providers.push("password".to_string());
+            }
+            let manageable = user.id != logged_user.id && logged_user.role.can_manage(user.role);
+            let roles: Vec<_> = ROLES
+                .iter()
+                .filter(|role| logged_user.role.can_assign(**role))
+                .map(|role| {
+                    json!({
+                        "value": role,
+                        "name": role.display_name(),
+                        "selected": *role == user.role,
+                    })
+                })
+                .collect();
+            json!({
+                "id": user.id.to_simple().to_string(),
+                "username": user.username,
+                "name": user.name,
+                "email": user.email,
+                "role": user.role.display_name(),
+                "providers": providers.join(", "),
+                "suspended": user.suspended,
+                "last_login": user.last_login_at.map(|l| l.format("%B %-d, %Y %H:%M")),
+                "created": user.created_at.format("%B %-d, %Y"),
+                "manageable": manageable,
+                "roles": roles,
+            })
+        })
+        .collect();
+    let last_page = (total + USERS_PER_PAGE - 1) / USERS_PER_PAGE;
+
+    let data = json!({
+        "user": logged_user,
+        "users": users,
+        "search": query.search(),
+        "total": total,
+        "page": page,
+        "last_page": last_page.max(1),
+        // the query the actions send the admin back to
+        "query": query.to_query(page),
+        "previous": if page > 1 { Some(query.to_query(page - 1)) } else { None },
+        "next": if page < last_page { Some(query.to_query(page + 1)) } else { None },
+        "error": error,
+        "notice": notice,
+        "csrf_token": csrf_token,
+    });
+    let body = hb.render("admin_users", &data).unwrap();
+
+    // the errors are about actions the admin isn't allowed to take
+    let status = match error {
+        Some(_) => StatusCode::FORBIDDEN,
+        None => StatusCode::OK,
+    };
+    HttpResponse::build(status).body(body)
+}
+
+// The form to suspend a user, with an error after invalid input.
+fn render_suspend(
+    hb: &Handlebars<'_>,
+    logged_user: &LoggedUser,
+    csrf_token: &CsrfToken,
+    user: &User,
+    query: &ListQuery,
+    error: Option<&str>,
+    status: StatusCode,
+) -> HttpResponse {
+    let tomorrow = time::OffsetDateTime::now_utc().date().next_day();
+    let data = json!({
+        "user": logged_user,
+        "suspended_user": {
+            "id": user.id.to_simple().to_string(),
+            "username": user.username,
+        },
+        "min_until": tomorrow.format("%F"),
+        "max_reason_length": MAX_REASON_LENGTH,
+        "query": query.to_query(query.page()),
+        "error": error,
+        "csrf_token": csrf_token,
+    });
+    let body = hb.render("admin_user_suspend", &data).unwrap();
+
+    HttpResponse::build(status).body(body)
+}
+
+// The choice of what happens to the posts and reviews of a user, with an error after a failed
+// deletion.
+async fn render_delete(
+    hb: &Handlebars<'_>,
+    logged_user: &LoggedUser,
+    csrf_token: &CsrfToken,
+    user: &User,
+    query: &ListQuery,
+    db_pool: &DbPool,
+    error: Option<&str>,
+) -> HttpResponse {
+    let (posts, reviews) = match UserDeletion::count_authored(user.id, db_pool).await {
+        Ok(counts) => counts,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let data = json!({
+        "user": logged_user,
+        "deleted_user": {
+            "id": user.id.to_simple().to_string(),
+            "username": user.username,
+        },
+        "posts": posts,
+        "reviews": reviews,
+        "has_history": posts > 0 || reviews > 0,
+        "query": query.to_query(query.page()),
+        "error": error,
+        "csrf_token": csrf_token,
+    });
+    let body = hb.render("admin_user_delete", &data).unwrap();
+
+    let status = match error {
+        Some(_) => StatusCode::BAD_REQUEST,
+        None => StatusCode::OK,
+    };
+    HttpResponse::build(status).body(body)
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(change_role);
+    cfg.service(suspend_page);
+    cfg.service(suspend);
+    cfg.service(unsuspend);
+    cfg.service(delete_page);
+    cfg.service(delete);
+}
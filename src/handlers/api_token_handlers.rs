@@ -0,0 +1,181 @@
+use crate::csrf::CsrfToken;
+use crate::database::DbPool;
+use crate::models::{ApiToken, Scope, User};
+use crate::session::LoggedUser;
+use actix_web::http::{header, StatusCode};
+use actix_web::{get, post, web, HttpResponse};
+use handlebars::Handlebars;
+use serde_json::json;
+use sqlx::types::Uuid;
+use time::Duration;
+
+// Personal api tokens, for scripts like CI jobs that use the json api. They're sent as
+// `Authorization: Bearer <token>`, see `LoggedUser`.
+
+/// Lists the tokens of the user along with the form to create one.
+#[get("/account/tokens")]
+async fn list(
+    logged_user: LoggedUser,
+    csrf_token: CsrfToken,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    render_tokens(
+        &hb,
+        &logged_user,
+        &csrf_token,
+        db_pool.get_ref(),
+        None,
+        None,
+        StatusCode::OK,
+    )
+    .await
+}
+
+/// Creates a token and shows it once. The form is a list of pairs since it has a `scope` for
+/// every checked scope.
+#[post("/account/tokens")]
+async fn create(
+    logged_user: LoggedUser,
+    csrf_token: CsrfToken,
+    form: web::Form<Vec<(String, String)>>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let mut name = "";
+    let mut expires_in = "";
+    let mut scopes = Vec::new();
+    for (key, value) in form.iter() {
+        match key.as_str() {
+            "name" => name = value.trim(),
+            "expires_in" => expires_in = value,
+            "scope" => match Scope::parse(value) {
+                Some(scope) if !scopes.contains(&scope) => scopes.push(scope),
+                Some(_) => {}
+                None => return HttpResponse::BadRequest().body("Invalid scope"),
+            },
+            _ => {}
+        }
+    }
+
+    let error = if name.is_empty() || name.chars().count() > 100 {
+        Some("The name has to be between 1 and 100 characters.")
+    } else if scopes.is_empty() {
+        Some("Choose at least one scope.")
+    } else {
+        None
+    };
+    if error.is_some() {
+        return render_tokens(
+            &hb,
+            &logged_user,
+            &csrf_token,
+            db_pool,
+            None,
+            error,
+            StatusCode::BAD_REQUEST,
+        )
+        .await;
+    }
+    let lifetime = match expires_in {
+        "never" => None,
+        days => match days.parse::<i64>() {
+            Ok(days) if [30, 90, 365].contains(&days) => Some(Duration::days(days)),
+            _ => return HttpResponse::BadRequest().body("Invalid expiration"),
+        },
+    };
+
+    match ApiToken::create(logged_user.id, name, &scopes, lifetime, db_pool).await {
+        Ok(token) => {
+            log::info!("{} created the api token {}", logged_user.username, name);
+            render_tokens(
+                &hb,
+                &logged_user,
+                &csrf_token,
+                db_pool,
+                Some(&token),
+                None,
+                StatusCode::OK,
+            )
+            .await
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[post("/account/tokens/{uuid}/revoke")]
+async fn revoke(
+    uuid: web::Path<String>,
+    logged_user: LoggedUser,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let uuid_;
+    match Uuid::parse_str(uuid.as_str()) {
+        Ok(u) => uuid_ = u,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid Token ID"),
+    }
+    match ApiToken::delete(uuid_, logged_user.id, db_pool.get_ref()).await {
+        Ok(rows) if rows > 0 => HttpResponse::Found()
+            .header(header::LOCATION, "/account/tokens".to_string())
+            .finish(),
+        _ => HttpResponse::BadRequest().body("Token not found"),
+    }
+}
+
+// The list of tokens and the form, with the token that was just created.
+async fn render_tokens(
+    hb: &Handlebars<'_>,
+    user: &User,
+    csrf_token: &CsrfToken,
+    db_pool: &DbPool,
+    new_token: Option<&str>,
+    error: Option<&str>,
+    status: StatusCode,
+) -> HttpResponse {
+    let now = time::OffsetDateTime::now_utc();
+    let now = time::PrimitiveDateTime::new(now.date(), now.time());
+    let tokens: Vec<_> = ApiToken::find_by_user_id(user.id, db_pool)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|token| {
+            json!({
+                "id": token.id.to_simple().to_string(),
+                "name": token.name,
+                "scopes": token.scopes.join(", "),
+                "created": token.created_at.format("%B %-d, %Y"),
+                "expires": token.expires_at.map(|e| e.format("%B %-d, %Y")),
+                "expired": matches!(token.expires_at, Some(e) if e <= now),
+                "last_used": token.last_used_at.map(|l| l.format("%B %-d, %Y %H:%M")),
+            })
+        })
+        .collect();
+    let scopes: Vec<_> = Scope::ALL
+        .iter()
+        .map(|scope| {
+            json!({
+                "name": scope.as_str(),
+                "description": scope.description(),
+            })
+        })
+        .collect();
+
+    let data = json!({
+        "user": user,
+        "tokens": tokens,
+        "scopes": scopes,
+        "new_token": new_token,
+        "error": error,
+        "csrf_token": csrf_token,
+    });
+    let body = hb.render("api_tokens", &data).unwrap();
+
+    HttpResponse::build(status).body(body)
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(create);
+    cfg.service(revoke);
+}
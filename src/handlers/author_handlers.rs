@@ -0,0 +1,222 @@
+use crate::csrf::CsrfToken;
+use crate::database::DbPool;
+use crate::models::user::Role;
+use crate::models::user_profile::MAX_BIO_LENGTH;
+use crate::models::{Post, ProfileRequest, User, UserProfile};
+use crate::session::{LoggedUser, SessionCache};
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, HttpResponse};
+use handlebars::Handlebars;
+use serde_json::json;
+
+const POSTS_PER_PAGE: i64 = 10;
+
+/// See the `name_length` constraint of the users table.
+const MAX_NAME_LENGTH: usize = 255;
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    page: Option<i64>,
+}
+
+/// The public page of an author with their profile and their published posts, newest first.
+#[get("/author/{username}")]
+async fn author(
+    username: web::Path<String>,
+    query: web::Query<PageQuery>,
+    logged_user: Option<LoggedUser>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let author = match User::find_by_username(username.as_str(), db_pool).await {
+        Ok(u) => u,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    let page = query.page.unwrap_or(1).max(1);
+    let (posts, total) =
+        match Post::find_published_by_user_id(author.id, page, POSTS_PER_PAGE, db_pool).await {
+            Ok(found) => found,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    // subscribers don't get a page, unless they wrote before their role was lowered
+    if total == 0 && author.role < Role::Contributor {
+        return HttpResponse::NotFound().finish();
+    }
+    let profile = match UserProfile::find_by_user_id(author.id, db_pool).await {
+        Ok(p) => p,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let posts: Vec<_> = posts
+        .iter()
+        .map(|post| {
+            json!({
+                "title": post.title,
+                "permalink": post.permalink(),
+                "excerpt": post.excerpt,
+                "published": post.published_at.unwrap_or(post.created_at).format("%B %-d, %Y"),
+            })
+        })
+        .collect();
+    let links = profile.as_ref().map_or_else(Vec::new, |profile| {
+        let mut links = Vec::new();
+        if let Some(website) = &profile.website {
+            links.push(json!({ "name": "Website", "url": website }));
+        }
+        if let Some(twitter) = &profile.twitter {
+            let url = format!("https://twitter.com/{}", twitter);
+            links.push(json!({ "name": "Twitter", "url": url }));
+        }
+        if let Some(github) = &profile.github {
+            let url = format!("https://github.com/{}", github);
+            links.push(json!({ "name": "GitHub", "url": url }));
+        }
+        if let Some(mastodon) = &profile.mastodon {
+            links.push(json!({ "name": "Mastodon", "url": mastodon }));
+        }
+        links
+    });
+    let last_page = (total + POSTS_PER_PAGE - 1) / POSTS_PER_PAGE;
+    let url = author.profile_url();
+
+    let data = json!({
+        "user": logged_user,
+        "author": {
+            "name": author.display_name(),
+            "username": author.username,
+            "avatar_url": author.avatar_url,
+            "bio": profile.as_ref().and_then(|p| p.bio.as_ref()),
+        },
+        "links": links,
+        "posts": posts,
+        "previous": if page > 1 { Some(format!("{}?page={}", url, page - 1)) } else { None },
+        "next": if page < last_page { Some(format!("{}?page={}", url, page + 1)) } else { None },
+    });
+    let body = hb.render("author", &data).unwrap();
+
+    HttpResponse::Ok().body(body)
+}
+
+/// The public profile of the logged in user.
+#[get("/account/profile")]
+async fn profile_page(
+    logged_user: LoggedUser,
+    csrf_token: CsrfToken,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let profile = match UserProfile::find_by_user_id(logged_user.id, db_pool.get_ref()).await {
+        Ok(Some(p)) => ProfileRequest::from(p),
+        Ok(None) => ProfileRequest::default(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let name = logged_user.name.clone();
+    render_profile(
+        &hb,
+        &logged_user,
+        &csrf_token,
+        name.as_deref(),
+        &profile,
+        None,
+        None,
+    )
+}
+
+#[derive(Deserialize)]
+pub struct ProfileForm {
+    name: String,
+    bio: String,
+    website: String,
+    twitter: String,
+    github: String,
+    mastodon: String,
+}
+
+#[post("/account/profile")]
+async fn update_profile(
+    logged_user: LoggedUser,
+    csrf_token: CsrfToken,
+    form: web::Form<ProfileForm>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let form = form.into_inner();
+    let name = form.name.trim();
+    let name = if name.is_empty() { None } else { Some(name) };
+    let profile = ProfileRequest {
+        bio: Some(form.bio),
+        website: Some(form.website),
+        twitter: Some(form.twitter),
+        github: Some(form.github),
+        mastodon: Some(form.mastodon),
+    }
+    .normalize();
+
+    let error = if name.map_or(false, |n| n.chars().count() > MAX_NAME_LENGTH) {
+        Some("The name can't be longer than 255 characters.")
+    } else {
+        profile.validate().err()
+    };
+    if error.is_some() {
+        return render_profile(&hb, &logged_user, &csrf_token, name, &profile, error, None);
+    }
+
+    if User::set_name(logged_user.id, name, db_pool).await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    cache.remove(logged_user.id);
+    let profile = match UserProfile::save(logged_user.id, profile, db_pool).await {
+        Ok(p) => ProfileRequest::from(p),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let mut user = logged_user;
+    user.name = name.map(|n| n.to_string());
+    render_profile(
+        &hb,
+        &user,
+        &csrf_token,
+        name,
+        &profile,
+        None,
+        Some("Your profile was saved."),
+    )
+}
+
+// The profile form, with an error after invalid input or a notice once it's saved.
+fn render_profile(
+    hb: &Handlebars,
+    user: &User,
+    csrf_token: &CsrfToken,
+    name: Option<&str>,
+    profile: &ProfileRequest,
+    error: Option<&str>,
+    success: Option<&str>,
+) -> HttpResponse {
+    let status = if error.is_some() {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::OK
+    };
+    let data = json!({
+        "user": user,
+        "csrf_token": csrf_token,
+        "profile_url": user.profile_url(),
+        "name": name,
+        "profile": profile,
+        "max_bio_length": MAX_BIO_LENGTH,
+        "error": error,
+        "success": success,
+    });
+    let body = hb.render("profile", &data).unwrap();
+
+    HttpResponse::build(status).body(body)
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(author);
+    cfg.service(profile_page);
+    cfg.service(update_profile);
+}
@@ -0,0 +1,331 @@
+use crate::csrf::CsrfToken;
+use crate::database::DbPool;
+use crate::mailer::Mailer;
+use crate::middleware::render_error;
+use crate::models::{Session, TokenPurpose, User, UserToken};
+use crate::password;
+use crate::rate_limit::{too_many_requests, Key, RateLimiter};
+use crate::session::{LoggedUser, SessionCache};
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use handlebars::Handlebars;
+use serde_json::json;
+
+// "Forgot password" and "verify your email" flows. Both send a link with a single use token,
+// see `UserToken`. Password resets are only sent to verified addresses, so an address a
+// provider gave us can't be used to take over the account.
+
+#[get("/password/forgot")]
+async fn forgot_password_page(
+    csrf_token: CsrfToken,
+    hb: web::Data<Handlebars<'_>>,
+) -> HttpResponse {
+    let data = json!({
+        "user": null,
+        "csrf_token": csrf_token,
+        "sent": false,
+    });
+    let body = hb.render("forgot_password", &data).unwrap();
+
+    HttpResponse::Ok().body(body)
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    email: String,
+}
+
+/// Sends the reset link in the background, the answer comes as quickly whether there's an account
+/// or not.
+#[post("/password/forgot")]
+async fn forgot_password(
+    form: web::Form<ForgotPasswordRequest>,
+    hb: web::Data<Handlebars<'static>>,
+    mailer: web::Data<Mailer>,
+    db_pool: web::Data<DbPool>,
+    limiter: web::Data<RateLimiter>,
+) -> HttpResponse {
+    // every request counts, so nobody's inbox can be flooded with reset links
+    let key = Key::Account(format!("email:{}", form.email.trim().to_lowercase()));
+    if let Some(wait) = limiter.check(&key).await {
+        return too_many_requests(wait);
+    }
+    limiter.fail(&key).await;
+
+    if let Ok(user) = User::find_by_verified_email(form.email.trim(), db_pool.get_ref()).await {
+        let hb = hb.clone();
+        actix_web::rt::spawn(async move {
+            // the address as it's stored, the lookup ignores case
+            let email = user.email.as_deref().unwrap_or_default();
+            let result = async {
+                let token = UserToken::create(
+                    user.id,
+                    TokenPurpose::PasswordReset,
+                    Some(email),
+                    db_pool.get_ref(),
+                )
+                .await?;
+                let data = json!({
+                    "username": user.username,
+                    "url": format!("{}/password/reset/{}", mailer.base_url, token),
+                });
+                mailer
+                    .send(&hb, email, "Reset your password", "password_reset", &data)
+                    .await
+            }
+            .await;
+            if let Err(e) = result {
+                log::error!("Failed to send password reset email: {}", e);
+            }
+        });
+    }
+
+    // the same answer whether there's an account or not, so addresses can't be probed
+    let data = json!({
+        "user": null,
+        "sent": true,
+    });
+    let body = hb.render("forgot_password", &data).unwrap();
+
+    HttpResponse::Ok().body(body)
+}
+
+#[get("/password/reset/{token}")]
+async fn reset_password_page(
+    req: HttpRequest,
+    csrf_token: CsrfToken,
+    token: web::Path<String>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    match UserToken::find_user_id(&token, TokenPurpose::PasswordReset, db_pool.get_ref()).await {
+        Ok(Some(_)) => render_reset_password(&hb, &csrf_token, &token, None, StatusCode::OK),
+        _ => expired_link(&req),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    password: String,
+    password_confirmation: String,
+}
+
+/// Saves the new password and signs the user out everywhere, whoever asked for the reset
+/// might have been signed in as them.
+#[post("/password/reset/{token}")]
+async fn reset_password(
+    req: HttpRequest,
+    csrf_token: CsrfToken,
+    token: web::Path<String>,
+    form: web::Form<ResetPasswordRequest>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let user = match UserToken::find_user_id(&token, TokenPurpose::PasswordReset, db_pool).await {
+        Ok(Some(user_id)) => match User::find_by_id(user_id, db_pool).await {
+            Ok(u) => u,
+            Err(_) => return expired_link(&req),
+        },
+        _ => return expired_link(&req),
+    };
+
+    let form = form.into_inner();
+    if form.password != form.password_confirmation {
+        return render_reset_password(
+            &hb,
+            &csrf_token,
+            &token,
+            Some("The passwords don't match."),
+            StatusCode::BAD_REQUEST,
+        );
+    }
+    if let Err(e) = password::check_strength(&form.password, &user.username) {
+        return render_reset_password(&hb, &csrf_token, &token, Some(e), StatusCode::BAD_REQUEST);
+    }
+    let hash = match password::hash(form.password).await {
+        Ok(h) => h,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    // only now the token is used up, so a typo in the form doesn't need a new email
+    let used = match UserToken::consume(&token, TokenPurpose::PasswordReset, db_pool).await {
+        Ok(Some(t)) if t.user_id == user.id => t,
+        Ok(_) => return expired_link(&req),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let result = async {
+        User::set_password(user.id, &hash, db_pool).await?;
+        Session::delete_by_user_id(user.id, db_pool).await?;
+        User::bump_session_version(user.id, db_pool).await?;
+        // getting the email proves the user owns the address
+        if let Some(email) = used.email {
+            User::verify_email(user.id, &email, db_pool).await?;
+        }
+        Ok::<(), anyhow::Error>(())
+    }
+    .await;
+    cache.remove(user.id);
+    cache.remove_user_sessions(user.id);
+    if result.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    render_notice(
+        &hb,
+        "Password changed",
+        "Your password has been changed, you can sign in with it now.",
+    )
+}
+
+fn render_reset_password(
+    hb: &Handlebars,
+    csrf_token: &CsrfToken,
+    token: &str,
+    error: Option<&str>,
+    status: StatusCode,
+) -> HttpResponse {
+    let data = json!({
+        "user": null,
+        "csrf_token": csrf_token,
+        "token": token,
+        "error": error,
+    });
+    let body = hb.render("reset_password", &data).unwrap();
+
+    HttpResponse::build(status).body(body)
+}
+
+/// Shows the email address of the user and whether it's verified.
+#[get("/account/email")]
+async fn email_page(
+    logged_user: LoggedUser,
+    csrf_token: CsrfToken,
+    hb: web::Data<Handlebars<'_>>,
+) -> HttpResponse {
+    render_email(&hb, &logged_user, &csrf_token, false)
+}
+
+#[post("/account/email/verify")]
+async fn send_verification(
+    logged_user: LoggedUser,
+    csrf_token: CsrfToken,
+    hb: web::Data<Handlebars<'_>>,
+    mailer: web::Data<Mailer>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    if logged_user.email.is_none() || logged_user.email_verified {
+        return HttpResponse::BadRequest().body("There is no email address to verify");
+    }
+    if let Err(e) = send_verification_email(&logged_user, &hb, &mailer, db_pool.get_ref()).await {
+        log::error!("Failed to send verification email: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    render_email(&hb, &logged_user, &csrf_token, true)
+}
+
+#[get("/account/email/verify/{token}")]
+async fn verify_email(
+    req: HttpRequest,
+    token: web::Path<String>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+) -> HttpResponse {
+    let used = match UserToken::consume(&token, TokenPurpose::EmailVerification, db_pool.get_ref())
+        .await
+    {
+        Ok(Some(t)) => t,
+        Ok(None) => return expired_link(&req),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let email = used.email.unwrap_or_default();
+    let result = User::verify_email(used.user_id, &email, db_pool.get_ref()).await;
+    cache.remove(used.user_id);
+    match result {
+        Ok(rows) if rows > 0 => render_notice(
+            &hb,
+            "Email verified",
+            &format!("Thanks, {} is verified now.", email),
+        ),
+        // the address was changed after the email was sent
+        Ok(_) => expired_link(&req),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Emails the user a link to verify their address.
+pub async fn send_verification_email(
+    user: &User,
+    hb: &Handlebars<'_>,
+    mailer: &Mailer,
+    db_pool: &DbPool,
+) -> anyhow::Result<()> {
+    let email = match &user.email {
+        Some(e) => e,
+        None => return Ok(()),
+    };
+    let token = UserToken::create(
+        user.id,
+        TokenPurpose::EmailVerification,
+        Some(email),
+        db_pool,
+    )
+    .await?;
+    let data = json!({
+        "username": user.username,
+        "url": format!("{}/account/email/verify/{}", mailer.base_url, token),
+    });
+    mailer
+        .send(
+            hb,
+            email,
+            "Verify your email address",
+            "verify_email",
+            &data,
+        )
+        .await
+}
+
+fn render_email(hb: &Handlebars, user: &User, csrf_token: &CsrfToken, sent: bool) -> HttpResponse {
+    let data = json!({
+        "user": user,
+        "csrf_token": csrf_token,
+        "sent": sent,
+    });
+    let body = hb.render("email", &data).unwrap();
+
+    HttpResponse::Ok().body(body)
+}
+
+pub fn render_notice(hb: &Handlebars, title: &str, message: &str) -> HttpResponse {
+    let data = json!({
+        "user": null,
+        "title": title,
+        "message": message,
+    });
+    let body = hb.render("notice", &data).unwrap();
+
+    HttpResponse::Ok().body(body)
+}
+
+fn expired_link(req: &HttpRequest) -> HttpResponse {
+    render_error(
+        req,
+        StatusCode::BAD_REQUEST,
+        "This link has expired or was used already.",
+        "Invalid link",
+        None,
+    )
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(forgot_password_page);
+    cfg.service(forgot_password);
+    cfg.service(reset_password_page);
+    cfg.service(reset_password);
+    cfg.service(email_page);
+    cfg.service(send_verification);
+    cfg.service(verify_email);
+}
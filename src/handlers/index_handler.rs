@@ -1,21 +1,55 @@
-use crate::models::user::ToUser;
-use actix_identity::Identity;
+use crate::database::DbPool;
+use crate::models::{Post, User};
+use crate::session::LoggedUser;
 use actix_web::{get, web, HttpResponse};
 use handlebars::Handlebars;
 use serde_json::json;
 
+// TODO pagination
 #[get("/")]
-fn index(id: Identity, hb: web::Data<Handlebars<'_>>) -> HttpResponse {
-    let logged_user = id.user();
+async fn index(
+    logged_user: Option<LoggedUser>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let posts = Post::find_published(Some(10), db_pool.get_ref())
+        .await
+        .unwrap_or_default();
+    let author_ids: Vec<_> = posts.iter().map(|post| post.user_id).collect();
+    let authors = User::find_by_ids(&author_ids, db_pool.get_ref())
+        .await
+        .unwrap_or_default();
+    let posts: Vec<_> = posts
+        .iter()
+        .map(|post| {
+            let author = authors.iter().find(|author| author.id == post.user_id);
+            json!({
+                "title": post.title,
+                "permalink": post.permalink(),
+                "excerpt": post.excerpt,
+                "published": post.published_at.unwrap_or(post.created_at).format("%B %-d, %Y"),
+                "author": author.map(byline),
+            })
+        })
+        .collect();
 
     let data = json!({
         "user": &logged_user,
+        "posts": posts,
     });
     let body = hb.render("index", &data).unwrap();
 
     HttpResponse::Ok().body(body)
 }
 
+/// The name of the author of a post, linked to their page.
+pub fn byline(author: &User) -> serde_json::Value {
+    json!({
+        "name": author.display_name(),
+        "url": author.profile_url(),
+    })
+}
+
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(index);
 }
@@ -0,0 +1,295 @@
+use crate::csrf::CsrfToken;
+use crate::database::DbPool;
+use crate::handlers::account_handlers::registration_open;
+use crate::login::{sync_profile, two_factor};
+use crate::login::{AuthProvider, AuthProviders, AuthRequest, LoginError, PendingLogin, Profile};
+use crate::middleware::render_suspended;
+use crate::models::{User, UserIdentity};
+use crate::password;
+use crate::rate_limit::{too_many_requests, Key, RateLimiter};
+use crate::session::{self, safe_return_to, LoggedUser, SessionCache};
+use actix_identity::Identity;
+use actix_session::Session;
+use actix_web::http::{header, StatusCode};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use handlebars::Handlebars;
+use oauth2::AuthorizationCode;
+use serde_json::json;
+use url::form_urlencoded;
+
+#[derive(Deserialize)]
+pub struct ReturnTo {
+    return_to: Option<String>,
+}
+
+/// Lists every enabled provider to sign in with, along with the password form.
+#[get("/login")]
+async fn login_page(
+    logged_user: Option<LoggedUser>,
+    csrf_token: CsrfToken,
+    providers: web::Data<AuthProviders>,
+    hb: web::Data<Handlebars<'_>>,
+    params: web::Query<ReturnTo>,
+) -> HttpResponse {
+    let return_to = safe_return_to(params.return_to.as_deref());
+    if logged_user.is_some() {
+        return HttpResponse::Found()
+            .header(header::LOCATION, return_to)
+            .finish();
+    }
+    render_login(
+        &hb,
+        &csrf_token,
+        &providers,
+        &return_to,
+        "",
+        None,
+        StatusCode::OK,
+    )
+}
+
+#[derive(Deserialize)]
+pub struct PasswordLogin {
+    username: String,
+    password: String,
+    return_to: Option<String>,
+}
+
+/// Signs in with the username and password of a local account.
+#[post("/login")]
+async fn password_login(
+    req: HttpRequest,
+    id: Identity,
+    session: Session,
+    csrf_token: CsrfToken,
+    form: web::Form<PasswordLogin>,
+    providers: web::Data<AuthProviders>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+    limiter: web::Data<RateLimiter>,
+) -> HttpResponse {
+    let form = form.into_inner();
+    let return_to = safe_return_to(form.return_to.as_deref());
+    // by the name that was typed, so unknown usernames are limited the same way
+    let key = Key::Account(format!("login:{}", form.username.trim().to_lowercase()));
+    if let Some(wait) = limiter.check(&key).await {
+        return too_many_requests(wait);
+    }
+    let user = User::find_by_username(form.username.trim(), db_pool.get_ref())
+        .await
+        .ok();
+    let hash = user.as_ref().and_then(|u| u.password.clone());
+
+    // the same error for unknown users and wrong passwords, so usernames can't be probed
+    let user = match user {
+        Some(u) if password::verify(hash, form.password).await => u,
+        _ => {
+            limiter.fail(&key).await;
+            return render_login(
+                &hb,
+                &csrf_token,
+                &providers,
+                &return_to,
+                &form.username,
+                Some("Wrong username or password."),
+                StatusCode::UNAUTHORIZED,
+            );
+        }
+    };
+    limiter.clear(&key).await;
+
+    match two_factor::begin(&req, &id, &session, &user, return_to, db_pool.get_ref()).await {
+        Ok(location) => HttpResponse::Found()
+            .header(header::LOCATION, location)
+            .finish(),
+        Err(LoginError::Suspended(suspension)) => render_suspended(&req, &suspension),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Renders the login page, with an error after a failed password login.
+fn render_login(
+    hb: &Handlebars,
+    csrf_token: &CsrfToken,
+    providers: &AuthProviders,
+    return_to: &str,
+    username: &str,
+    error: Option<&str>,
+    status: StatusCode,
+) -> HttpResponse {
+    let query: String = form_urlencoded::Serializer::new(String::new())
+        .append_pair("return_to", return_to)
+        .finish();
+    let providers: Vec<_> = providers
+        .iter()
+        .map(|provider| {
+            json!({
+                "name": provider.name(),
+                "url": format!("/login/{}?{}", provider.id(), query),
+            })
+        })
+        .collect();
+    let registration_url = if registration_open() {
+        Some(format!("/register?{}", query))
+    } else {
+        None
+    };
+
+    let data = json!({
+        "user": null,
+        "providers": providers,
+        "return_to": return_to,
+        "username": username,
+        "error": error,
+        "registration_url": registration_url,
+        "csrf_token": csrf_token,
+    });
+    let body = hb.render("login", &data).unwrap();
+
+    HttpResponse::build(status).body(body)
+}
+
+/// Sends the user to the provider, which redirects back to its callback.
+#[get("/login/{provider}")]
+async fn login(
+    req: HttpRequest,
+    provider: web::Path<String>,
+    providers: web::Data<AuthProviders>,
+    session: Session,
+    params: web::Query<ReturnTo>,
+) -> HttpResponse {
+    let provider = match providers.get(provider.as_str()) {
+        Some(p) => p,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    // all of them are checked in the callback, starting a new login replaces the previous one
+    let pending = PendingLogin::new(provider.id(), safe_return_to(params.return_to.as_deref()));
+    let auth_url = match provider.authorize_url(&pending).await {
+        Ok(url) => url,
+        Err(e) => return e.error_page(&req, provider.name()),
+    };
+    if pending.save(&session).is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Found()
+        .header(header::LOCATION, auth_url.to_string())
+        .finish()
+}
+
+/// Registered at the path of each provider's callback url.
+async fn callback(
+    req: HttpRequest,
+    id: Identity,
+    session: Session,
+    providers: web::Data<AuthProviders>,
+    params: web::Query<AuthRequest>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let provider = match providers.by_callback_path(req.path()) {
+        Some(p) => p,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    match sign_in(
+        &req,
+        &id,
+        &session,
+        provider,
+        params.into_inner(),
+        db_pool.get_ref(),
+    )
+    .await
+    {
+        Ok(return_to) => HttpResponse::Found()
+            .header(header::LOCATION, return_to)
+            .finish(),
+        Err(e) => e.error_page(&req, provider.name()),
+    }
+}
+
+// Logs the user in, or asks for their second factor, and returns where to redirect them to.
+async fn sign_in(
+    req: &HttpRequest,
+    id: &Identity,
+    session: &Session,
+    provider: &dyn AuthProvider,
+    params: AuthRequest,
+    db_pool: &DbPool,
+) -> Result<String, LoginError> {
+    // The login has to have been started from this browser, and each one can only be used once.
+    let pending = PendingLogin::take(session, provider.id())?;
+    let code = AuthorizationCode::new(params.verify(&pending)?);
+
+    let token = provider.exchange_code(code, &pending).await?;
+    let profile = provider.fetch_profile(&token, &pending).await?;
+
+    let identity = match UserIdentity::find(provider.id(), &profile.subject, db_pool).await {
+        Ok(i) => i,
+        Err(_) => find_or_create_user(provider.id(), &profile, db_pool)
+            .await
+            .map_err(|_| LoginError::Database)?,
+    };
+    // the provider's data may have changed since the last login, the token always has
+    let user = sync_profile(&identity, &profile, db_pool)
+        .await
+        .map_err(|_| LoginError::Database)?;
+
+    two_factor::begin(req, id, session, &user, pending.return_to, db_pool).await
+}
+
+// Creates the user for an account that signs in for the first time, and links it.
+async fn find_or_create_user(
+    provider: &str,
+    profile: &Profile,
+    db_pool: &DbPool,
+) -> anyhow::Result<UserIdentity> {
+    // GitHub users used to be looked up by `github_id` only
+    let existing = match profile.user.github_id {
+        Some(github_id) => User::find_by_github_id(github_id, db_pool).await.ok(),
+        None => None,
+    };
+    let user = match existing {
+        Some(u) => u,
+        None => {
+            let mut request = profile.user.clone();
+            request.username = User::unique_username(&request.username, db_pool).await?;
+            User::create(request, db_pool).await?
+        }
+    };
+    let identity = UserIdentity::create(user.id, provider, &profile.subject, db_pool).await?;
+    Ok(identity)
+}
+
+#[get("/logout")]
+async fn logout(
+    id: Identity,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+    params: web::Query<ReturnTo>,
+) -> HttpResponse {
+    if session::logout(&id, db_pool.get_ref(), cache.get_ref())
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Found()
+        .header(
+            header::LOCATION,
+            safe_return_to(params.return_to.as_deref()),
+        )
+        .finish()
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    let providers = AuthProviders::from_env();
+    for provider in providers.iter() {
+        cfg.route(provider.callback_path(), web::get().to(callback));
+    }
+    cfg.data(providers);
+    cfg.service(login_page);
+    cfg.service(password_login);
+    cfg.service(login);
+    cfg.service(logout);
+}
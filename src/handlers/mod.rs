@@ -1,21 +1,37 @@
-use crate::handlers::github_oauth2::github_oauth2_config;
 use actix_web::web;
 
+mod account_handlers;
+mod admin_user_handlers;
+mod api_token_handlers;
+mod author_handlers;
+mod email_handlers;
 mod favicon_handlers;
-mod github_oauth2;
 pub mod index_handler;
+mod login_handlers;
 mod post_handlers;
+mod review_handlers;
+mod session_handlers;
+mod setup_handlers;
+mod two_factor_handlers;
 mod user_handlers;
 
-pub use github_oauth2::GithubUserInfo;
-
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("")
             .configure(index_handler::init)
+            // before `/login/{provider}` so `/login/2fa` isn't taken for a provider
+            .configure(two_factor_handlers::init)
+            .configure(login_handlers::init)
+            .configure(account_handlers::init)
+            .configure(email_handlers::init)
             .configure(user_handlers::init)
+            .configure(admin_user_handlers::init)
             .configure(post_handlers::init)
-            .configure(favicon_handlers::init)
-            .configure(github_oauth2_config),
+            .configure(author_handlers::init)
+            .configure(review_handlers::init)
+            .configure(session_handlers::init)
+            .configure(api_token_handlers::init)
+            .configure(setup_handlers::init)
+            .configure(favicon_handlers::init),
     );
 }
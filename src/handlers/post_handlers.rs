@@ -1,16 +1,18 @@
 use crate::database::DbPool;
-use crate::models::user::Role;
+use crate::handlers::index_handler::byline;
+use crate::middleware::{roles, RequireRole};
 use crate::models::{Post, PostRequest, User};
-use actix_identity::Identity;
+use crate::session::LoggedUser;
 use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use handlebars::Handlebars;
+use serde_json::json;
 use sqlx::types::Uuid;
-
-// TODO setup proper middleware/route protection for each of the handlers
+use time::Date;
 
 // TODO pagination
 #[get("/posts")]
 async fn find_all(db_pool: web::Data<DbPool>) -> impl Responder {
-    let result = Post::find_all(db_pool.get_ref()).await;
+    let result = Post::find_published(None, db_pool.get_ref()).await;
     match result {
         Ok(posts) => HttpResponse::Ok().json(posts),
         _ => HttpResponse::BadRequest().body("Error trying to read all posts from database"),
@@ -18,7 +20,11 @@ async fn find_all(db_pool: web::Data<DbPool>) -> impl Responder {
 }
 
 #[get("/post/{uuid}")]
-async fn find(uuid: web::Path<String>, db_pool: web::Data<DbPool>) -> impl Responder {
+async fn find(
+    uuid: web::Path<String>,
+    db_pool: web::Data<DbPool>,
+    logged_user: Option<LoggedUser>,
+) -> impl Responder {
     let uuid_;
     match Uuid::parse_str(uuid.as_str()) {
         Ok(u) => uuid_ = u,
@@ -26,31 +32,61 @@ async fn find(uuid: web::Path<String>, db_pool: web::Data<DbPool>) -> impl Respo
     }
     let result = Post::find_by_id(uuid_, db_pool.get_ref()).await;
     match result {
-        Ok(post) => HttpResponse::Ok().json(post),
+        Ok(post) if post.can_be_read_by(logged_user.as_ref()) => HttpResponse::Ok().json(post),
         _ => HttpResponse::BadRequest().body("Post not found"),
     }
 }
 
+/// The public html page of a post, e.g. `/2020/11/21/my-first-post`.
+#[get("/{year:[0-9]{4}}/{month:[0-9]{2}}/{day:[0-9]{2}}/{slug}")]
+async fn permalink(
+    path: web::Path<(i32, u8, u8, String)>,
+    db_pool: web::Data<DbPool>,
+    hb: web::Data<Handlebars<'_>>,
+    logged_user: Option<LoggedUser>,
+) -> HttpResponse {
+    let (year, month, day, slug) = path.into_inner();
+    // an invalid date can never match a post, the error handlers will render the 404 page
+    let date = match Date::try_from_ymd(year, month, day) {
+        Ok(d) => d,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    let post = match Post::find_by_permalink(date, &slug, db_pool.get_ref()).await {
+        Ok(p) => p,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+
+    let author = User::find_by_id(post.user_id, db_pool.get_ref()).await.ok();
+
+    let data = json!({
+        "user": logged_user,
+        "post": &post,
+        "author": author.as_ref().map(byline),
+        "content": post.html(),
+        "published": post.published_at.unwrap_or(post.created_at).format("%B %-d, %Y"),
+    });
+    let body = hb.render("post", &data).unwrap();
+
+    HttpResponse::Ok().body(body)
+}
+
 #[post("/post")]
 async fn create(
     post: web::Json<PostRequest>,
     db_pool: web::Data<DbPool>,
-    id: Identity,
+    logged_user: RequireRole<roles::Contributor>,
 ) -> impl Responder {
-    let identity = match id.identity() {
-        None => return HttpResponse::Unauthorized().body("Unauthorized"),
-        Some(i) => i,
-    };
-
-    let logged_user = match serde_json::from_str::<User>(&*identity) {
-        Ok(u) => {
-            if u.role != Role::Admin || u.role != Role::SuperAdmin || u.role != Role::Author {
-                return HttpResponse::Unauthorized().body("Unauthorized");
-            };
-            u
-        }
-        Err(_) => return HttpResponse::Unauthorized().body("Unauthorized"),
-    };
+    let logged_user = logged_user.into_inner();
+    if let Err(e) = post.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    if !post
+        .status
+        .unwrap_or_default()
+        .can_be_set_by(logged_user.role)
+    {
+        return HttpResponse::Forbidden().body("Contributors can only save drafts");
+    }
 
     let result = Post::create(post.into_inner(), db_pool.get_ref(), logged_user).await;
     match result {
@@ -64,12 +100,30 @@ async fn update(
     uuid: web::Path<String>,
     post: web::Json<PostRequest>,
     db_pool: web::Data<DbPool>,
+    logged_user: RequireRole<roles::Contributor>,
 ) -> impl Responder {
     let uuid_;
     match Uuid::parse_str(uuid.as_str()) {
         Ok(u) => uuid_ = u,
         Err(_) => return HttpResponse::BadRequest().body("Invalid Post ID"),
     }
+    if let Err(e) = post.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    match Post::find_by_id(uuid_, db_pool.get_ref()).await {
+        Ok(p) if p.can_be_edited_by(&logged_user) => {
+            // a post that was submitted or published can't be changed behind the reviewers' back
+            if !post
+                .status
+                .unwrap_or(p.status)
+                .can_be_set_by(logged_user.role)
+            {
+                return HttpResponse::Forbidden().body("Contributors can only save drafts");
+            }
+        }
+        Ok(_) => return HttpResponse::Forbidden().body("You can only edit your own posts"),
+        Err(_) => return HttpResponse::BadRequest().body("Post not found"),
+    }
     let result = Post::update(uuid_, post.into_inner(), db_pool.get_ref()).await;
     match result {
         Ok(post) => HttpResponse::Ok().json(post),
@@ -78,12 +132,21 @@ async fn update(
 }
 
 #[delete("/post/{uuid}")]
-async fn delete(uuid: web::Path<String>, db_pool: web::Data<DbPool>) -> impl Responder {
+async fn delete(
+    uuid: web::Path<String>,
+    db_pool: web::Data<DbPool>,
+    logged_user: RequireRole<roles::Contributor>,
+) -> impl Responder {
     let uuid_;
     match Uuid::parse_str(uuid.as_str()) {
         Ok(u) => uuid_ = u,
         Err(_) => return HttpResponse::BadRequest().body("Invalid Post ID"),
     }
+    match Post::find_by_id(uuid_, db_pool.get_ref()).await {
+        Ok(p) if p.can_be_deleted_by(&logged_user) => {}
+        Ok(_) => return HttpResponse::Forbidden().body("You can only delete your own posts"),
+        Err(_) => return HttpResponse::BadRequest().body("Post not found"),
+    }
     let result = Post::delete(uuid_, db_pool.get_ref()).await;
     match result {
         Ok(rows) => {
@@ -104,4 +167,5 @@ pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(create);
     cfg.service(update);
     cfg.service(delete);
+    cfg.service(permalink);
 }
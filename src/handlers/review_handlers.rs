@@ -0,0 +1,180 @@
+use crate::database::DbPool;
+use crate::middleware::{roles, RequireRole};
+use crate::models::{Post, PostReview, PostStatus, ReviewAction, ReviewRequest};
+use crate::session::LoggedUser;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use sqlx::types::Uuid;
+
+// Editorial workflow for posts written by contributors. A contributor submits their draft, an
+// editor then approves it, requests changes or rejects it. Every step is kept in `post_reviews`.
+
+// TODO pagination
+#[get("/posts/pending")]
+async fn queue(
+    db_pool: web::Data<DbPool>,
+    _logged_user: RequireRole<roles::Editor>,
+) -> impl Responder {
+    let result = Post::find_by_status(PostStatus::PendingReview, db_pool.get_ref()).await;
+    match result {
+        Ok(posts) => HttpResponse::Ok().json(posts),
+        _ => HttpResponse::BadRequest().body("Error trying to read pending posts from database"),
+    }
+}
+
+/// The review history of a post including the notes of the reviewers. Only visible to the
+/// author of the post and editors.
+#[get("/post/{uuid}/reviews")]
+async fn history(
+    uuid: web::Path<String>,
+    db_pool: web::Data<DbPool>,
+    logged_user: LoggedUser,
+) -> impl Responder {
+    let uuid_;
+    match Uuid::parse_str(uuid.as_str()) {
+        Ok(u) => uuid_ = u,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid Post ID"),
+    }
+    let post = match Post::find_by_id(uuid_, db_pool.get_ref()).await {
+        Ok(p) => p,
+        Err(_) => return HttpResponse::BadRequest().body("Post not found"),
+    };
+    if !post.can_be_edited_by(&logged_user) {
+        return HttpResponse::Forbidden().body("Forbidden");
+    }
+    let result = PostReview::find_by_post_id(post.id, db_pool.get_ref()).await;
+    match result {
+        Ok(reviews) => HttpResponse::Ok().json(reviews),
+        _ => HttpResponse::BadRequest().body("Error trying to read reviews from database"),
+    }
+}
+
+#[post("/post/{uuid}/submit")]
+async fn submit(
+    uuid: web::Path<String>,
+    review: Option<web::Json<ReviewRequest>>,
+    db_pool: web::Data<DbPool>,
+    logged_user: RequireRole<roles::Contributor>,
+) -> impl Responder {
+    let uuid_;
+    match Uuid::parse_str(uuid.as_str()) {
+        Ok(u) => uuid_ = u,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid Post ID"),
+    }
+    match Post::find_by_id(uuid_, db_pool.get_ref()).await {
+        Ok(p) if p.user_id == logged_user.id => {}
+        Ok(_) => return HttpResponse::Forbidden().body("Only the author can submit a post"),
+        Err(_) => return HttpResponse::BadRequest().body("Post not found"),
+    }
+    let note = review.map(|r| r.into_inner()).unwrap_or_default().note;
+    transition(
+        uuid_,
+        logged_user.id,
+        ReviewAction::Submitted,
+        note,
+        db_pool.get_ref(),
+    )
+    .await
+}
+
+#[post("/post/{uuid}/approve")]
+async fn approve(
+    uuid: web::Path<String>,
+    review: Option<web::Json<ReviewRequest>>,
+    db_pool: web::Data<DbPool>,
+    logged_user: RequireRole<roles::Editor>,
+) -> impl Responder {
+    let note = review.map(|r| r.into_inner()).unwrap_or_default().note;
+    review_action(
+        uuid.as_str(),
+        ReviewAction::Approved,
+        note,
+        db_pool.get_ref(),
+        &logged_user,
+    )
+    .await
+}
+
+#[post("/post/{uuid}/request_changes")]
+async fn request_changes(
+    uuid: web::Path<String>,
+    review: web::Json<ReviewRequest>,
+    db_pool: web::Data<DbPool>,
+    logged_user: RequireRole<roles::Editor>,
+) -> impl Responder {
+    // the author needs to know what to change
+    let note = match review.into_inner().note {
+        Some(n) if !n.trim().is_empty() => n,
+        _ => return HttpResponse::BadRequest().body("A note is required when requesting changes"),
+    };
+    review_action(
+        uuid.as_str(),
+        ReviewAction::ChangesRequested,
+        Some(note),
+        db_pool.get_ref(),
+        &logged_user,
+    )
+    .await
+}
+
+#[post("/post/{uuid}/reject")]
+async fn reject(
+    uuid: web::Path<String>,
+    review: Option<web::Json<ReviewRequest>>,
+    db_pool: web::Data<DbPool>,
+    logged_user: RequireRole<roles::Editor>,
+) -> impl Responder {
+    let note = review.map(|r| r.into_inner()).unwrap_or_default().note;
+    review_action(
+        uuid.as_str(),
+        ReviewAction::Rejected,
+        note,
+        db_pool.get_ref(),
+        &logged_user,
+    )
+    .await
+}
+
+// Shared part of the editor actions.
+async fn review_action(
+    uuid: &str,
+    action: ReviewAction,
+    note: Option<String>,
+    db_pool: &DbPool,
+    logged_user: &LoggedUser,
+) -> HttpResponse {
+    let uuid_;
+    match Uuid::parse_str(uuid) {
+        Ok(u) => uuid_ = u,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid Post ID"),
+    }
+    transition(uuid_, logged_user.id, action, note, db_pool).await
+}
+
+async fn transition(
+    post_id: Uuid,
+    user_id: Uuid,
+    action: ReviewAction,
+    note: Option<String>,
+    db_pool: &DbPool,
+) -> HttpResponse {
+    let result = PostReview::create(post_id, user_id, action, note, db_pool).await;
+    match result {
+        Ok(Some(review)) => HttpResponse::Ok().json(review),
+        Ok(None) => match action {
+            ReviewAction::Submitted => {
+                HttpResponse::BadRequest().body("Only drafts can be submitted")
+            }
+            _ => HttpResponse::BadRequest().body("Post is not pending review"),
+        },
+        _ => HttpResponse::BadRequest().body("Error trying to save the review"),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(queue);
+    cfg.service(history);
+    cfg.service(submit);
+    cfg.service(approve);
+    cfg.service(request_changes);
+    cfg.service(reject);
+}
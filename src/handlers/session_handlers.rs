@@ -0,0 +1,73 @@
+use crate::csrf::CsrfToken;
+use crate::database::DbPool;
+use crate::models::Session;
+use crate::session::{LoggedUser, SessionCache, SessionIdentity};
+use actix_identity::Identity;
+use actix_web::http::header;
+use actix_web::{get, post, web, HttpResponse};
+use handlebars::Handlebars;
+use serde_json::json;
+use sqlx::types::Uuid;
+
+/// Lists the browsers the user is logged in on, so they can log out of the ones they don't
+/// recognize or don't use anymore.
+#[get("/sessions")]
+async fn list(
+    logged_user: LoggedUser,
+    csrf_token: CsrfToken,
+    id: Identity,
+    db_pool: web::Data<DbPool>,
+    hb: web::Data<Handlebars<'_>>,
+) -> HttpResponse {
+    let current = SessionIdentity::from_identity(&id).map(|s| s.session_id);
+    let sessions: Vec<_> = Session::find_by_user_id(logged_user.id, db_pool.get_ref())
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|session| {
+            json!({
+                "id": session.id.to_simple().to_string(),
+                "user_agent": session.user_agent.as_deref().unwrap_or("Unknown browser"),
+                "ip": session.ip.as_deref().unwrap_or("Unknown"),
+                "created": session.created_at.format("%B %-d, %Y"),
+                "last_seen": session.last_seen_at.format("%B %-d, %Y %H:%M"),
+                "current": Some(session.id) == current,
+            })
+        })
+        .collect();
+
+    let data = json!({
+        "user": logged_user,
+        "csrf_token": csrf_token,
+        "sessions": sessions,
+    });
+    let body = hb.render("sessions", &data).unwrap();
+
+    HttpResponse::Ok().body(body)
+}
+
+#[post("/sessions/{uuid}/revoke")]
+async fn revoke(
+    uuid: web::Path<String>,
+    logged_user: LoggedUser,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+) -> HttpResponse {
+    let uuid_;
+    match Uuid::parse_str(uuid.as_str()) {
+        Ok(u) => uuid_ = u,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid Session ID"),
+    }
+    cache.remove_session(uuid_);
+    match Session::delete(uuid_, logged_user.id, db_pool.get_ref()).await {
+        Ok(rows) if rows > 0 => HttpResponse::Found()
+            .header(header::LOCATION, "/sessions".to_string())
+            .finish(),
+        _ => HttpResponse::BadRequest().body("Session not found"),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(revoke);
+}
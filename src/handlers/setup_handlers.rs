@@ -0,0 +1,112 @@
+use crate::csrf::CsrfToken;
+use crate::database::DbPool;
+use crate::handlers::email_handlers::render_notice;
+use crate::models::user::Role;
+use crate::models::{Setup, User};
+use crate::session::{self, LoggedUser, SessionCache};
+use crate::setup::SetupToken;
+use actix_identity::Identity;
+use actix_web::http::header;
+use actix_web::{get, post, web, HttpResponse};
+use handlebars::Handlebars;
+use serde_json::json;
+use url::form_urlencoded;
+
+// The first-run setup, the link with the token is printed at startup until there's a super
+// admin. Anyone who can sign in and has the link can become the super admin.
+
+#[get("/setup/{token}")]
+async fn setup_page(
+    token: web::Path<String>,
+    logged_user: Option<LoggedUser>,
+    csrf_token: CsrfToken,
+    setup: web::Data<SetupToken>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    if !is_open(&token, &setup, db_pool.get_ref()).await {
+        return HttpResponse::NotFound().finish();
+    }
+    let logged_user = match logged_user {
+        Some(u) => u,
+        None => {
+            let query: String = form_urlencoded::Serializer::new(String::new())
+                .append_pair("return_to", &format!("/setup/{}", token))
+                .finish();
+            return HttpResponse::Found()
+                .header(header::LOCATION, format!("/login?{}", query))
+                .finish();
+        }
+    };
+
+    let data = json!({
+        "user": logged_user,
+        "csrf_token": csrf_token,
+        "token": token.as_str(),
+    });
+    let body = hb.render("setup", &data).unwrap();
+
+    HttpResponse::Ok().body(body)
+}
+
+/// Makes the logged in user the super admin and closes the setup for good.
+#[post("/setup/{token}")]
+async fn complete(
+    token: web::Path<String>,
+    logged_user: LoggedUser,
+    id: Identity,
+    setup: web::Data<SetupToken>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+) -> HttpResponse {
+    if !is_open(&token, &setup, db_pool.get_ref()).await {
+        return HttpResponse::NotFound().finish();
+    }
+    match Setup::complete(logged_user.id, db_pool.get_ref()).await {
+        Ok(true) => {}
+        // completed on another instance in the meantime
+        Ok(false) => {
+            setup.close();
+            return HttpResponse::NotFound().finish();
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    setup.close();
+    let logged_out =
+        User::require_two_factor_setup(logged_user.id, Role::SuperAdmin, db_pool.get_ref()).await;
+    cache.remove(logged_user.id);
+    log::info!("{} is the super admin now", logged_user.username);
+
+    let message = match logged_out {
+        Ok(false) => "You are the super admin now.",
+        Ok(true) => {
+            // the session is invalid already, this also drops the cookie
+            let _ = session::logout(&id, db_pool.get_ref(), cache.get_ref()).await;
+            "You are the super admin now. Super admins need two-factor authentication, sign in \
+            again to set it up."
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    render_notice(&hb, "Setup completed", message)
+}
+
+// Whether the token is right and no instance completed the setup yet.
+async fn is_open(token: &str, setup: &SetupToken, db_pool: &DbPool) -> bool {
+    if !setup.matches(token) {
+        return false;
+    }
+    match Setup::is_completed(db_pool).await {
+        Ok(false) => true,
+        Ok(true) => {
+            setup.close();
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(setup_page);
+    cfg.service(complete);
+}
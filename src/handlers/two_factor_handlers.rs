@@ -0,0 +1,432 @@
+use crate::csrf::CsrfToken;
+use crate::database::DbPool;
+use crate::login::two_factor::PendingTwoFactor;
+use crate::models::{TwoFactor, User};
+use crate::session::{self, LoggedUser};
+use crate::totp;
+use actix_identity::Identity;
+use actix_session::Session;
+use actix_web::http::{header, StatusCode};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use handlebars::Handlebars;
+use serde_json::json;
+
+/// Session key of the secret shown on the settings page until the user confirmed it.
+const SETUP_SECRET_KEY: &str = "two_factor_setup";
+
+#[derive(Deserialize)]
+pub struct CodeRequest {
+    code: String,
+}
+
+// Second step of the login, see `login::two_factor::begin`.
+
+#[get("/login/2fa")]
+async fn login_page(
+    cookie_session: Session,
+    csrf_token: CsrfToken,
+    hb: web::Data<Handlebars<'_>>,
+) -> HttpResponse {
+    if PendingTwoFactor::get(&cookie_session).is_none() {
+        return redirect("/login");
+    }
+    render_login(&hb, &csrf_token, None, StatusCode::OK)
+}
+
+#[post("/login/2fa")]
+async fn login(
+    req: HttpRequest,
+    id: Identity,
+    cookie_session: Session,
+    csrf_token: CsrfToken,
+    form: web::Form<CodeRequest>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let pending = match PendingTwoFactor::get(&cookie_session) {
+        Some(p) => p,
+        None => return redirect("/login"),
+    };
+    let two_factor = match TwoFactor::find_by_user_id(pending.user_id, db_pool).await {
+        Ok(Some(t)) => t,
+        // reset by an admin in the meantime
+        Ok(None) => return redirect("/login/2fa/setup"),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match check_code(&two_factor, &form.code, db_pool).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return render_login(
+                &hb,
+                &csrf_token,
+                Some("This code is not valid."),
+                StatusCode::UNAUTHORIZED,
+            )
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+
+    match finish_login(&req, &id, &cookie_session, &pending, db_pool).await {
+        Ok(_) => redirect(&pending.return_to),
+        Err(_) => redirect("/login"),
+    }
+}
+
+fn render_login(
+    hb: &Handlebars,
+    csrf_token: &CsrfToken,
+    error: Option<&str>,
+    status: StatusCode,
+) -> HttpResponse {
+    let data = json!({
+        "user": null,
+        "csrf_token": csrf_token,
+        "error": error,
+    });
+    let body = hb.render("two_factor", &data).unwrap();
+
+    HttpResponse::build(status).body(body)
+}
+
+/// Users whose role requires two-factor authentication set it up here before they're
+/// logged in for the first time.
+#[get("/login/2fa/setup")]
+async fn login_setup_page(
+    cookie_session: Session,
+    csrf_token: CsrfToken,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let mut pending = match PendingTwoFactor::get(&cookie_session) {
+        Some(p) => p,
+        None => return redirect("/login"),
+    };
+    let user = match User::find_by_id(pending.user_id, db_pool.get_ref()).await {
+        Ok(u) => u,
+        Err(_) => return redirect("/login"),
+    };
+    let secret = match pending.setup_secret.clone() {
+        Some(s) => s,
+        None => {
+            let secret = totp::generate_secret();
+            pending.setup_secret = Some(secret.clone());
+            if pending.save(&cookie_session).is_err() {
+                return HttpResponse::InternalServerError().finish();
+            }
+            secret
+        }
+    };
+    render_setup(&hb, None, &csrf_token, &user, &secret, None, StatusCode::OK)
+}
+
+#[post("/login/2fa/setup")]
+async fn login_setup(
+    req: HttpRequest,
+    id: Identity,
+    cookie_session: Session,
+    csrf_token: CsrfToken,
+    form: web::Form<CodeRequest>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let pending = match PendingTwoFactor::get(&cookie_session) {
+        Some(p) => p,
+        None => return redirect("/login"),
+    };
+    let secret = match &pending.setup_secret {
+        Some(s) => s,
+        None => return redirect("/login/2fa/setup"),
+    };
+    let step = match totp::verify(secret, &form.code) {
+        Some(step) => step,
+        None => {
+            return match User::find_by_id(pending.user_id, db_pool).await {
+                Ok(user) => render_setup(
+                    &hb,
+                    None,
+                    &csrf_token,
+                    &user,
+                    secret,
+                    Some("This code is not valid, check the time on your device."),
+                    StatusCode::BAD_REQUEST,
+                ),
+                Err(_) => redirect("/login"),
+            }
+        }
+    };
+
+    let codes = match TwoFactor::enable(pending.user_id, secret, step, db_pool).await {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match finish_login(&req, &id, &cookie_session, &pending, db_pool).await {
+        Ok(user) => render_recovery_codes(&hb, &user, &codes, &pending.return_to),
+        Err(_) => redirect("/login"),
+    }
+}
+
+// Logs in the user once they passed the second factor.
+async fn finish_login(
+    req: &HttpRequest,
+    id: &Identity,
+    cookie_session: &Session,
+    pending: &PendingTwoFactor,
+    db_pool: &DbPool,
+) -> anyhow::Result<User> {
+    PendingTwoFactor::remove(cookie_session);
+    let user = User::find_by_id(pending.user_id, db_pool).await?;
+    if user.session_version != pending.session_version {
+        anyhow::bail!("The sessions of the user were revoked during the login");
+    }
+    session::login(req, id, &user, db_pool).await?;
+    Ok(user)
+}
+
+// Settings of the logged in user.
+
+#[get("/account/2fa")]
+async fn settings(
+    logged_user: LoggedUser,
+    cookie_session: Session,
+    csrf_token: CsrfToken,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    render_settings(
+        &hb,
+        &logged_user,
+        &cookie_session,
+        &csrf_token,
+        db_pool.get_ref(),
+        None,
+    )
+    .await
+}
+
+#[post("/account/2fa/enable")]
+async fn enable(
+    logged_user: LoggedUser,
+    cookie_session: Session,
+    csrf_token: CsrfToken,
+    form: web::Form<CodeRequest>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    let secret = match cookie_session.get::<String>(SETUP_SECRET_KEY) {
+        Ok(Some(s)) => s,
+        _ => return redirect("/account/2fa"),
+    };
+    let step = match totp::verify(&secret, &form.code) {
+        Some(step) => step,
+        None => {
+            return render_settings(
+                &hb,
+                &logged_user,
+                &cookie_session,
+                &csrf_token,
+                db_pool,
+                Some("This code is not valid, check the time on your device."),
+            )
+            .await
+        }
+    };
+    cookie_session.remove(SETUP_SECRET_KEY);
+    match TwoFactor::enable(logged_user.id, &secret, step, db_pool).await {
+        Ok(codes) => render_recovery_codes(&hb, &logged_user, &codes, "/account/2fa"),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[post("/account/2fa/disable")]
+async fn disable(
+    logged_user: LoggedUser,
+    cookie_session: Session,
+    csrf_token: CsrfToken,
+    form: web::Form<CodeRequest>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    if logged_user.role.requires_two_factor() {
+        return HttpResponse::Forbidden().body("Your role requires two-factor authentication");
+    }
+    match confirm_code(&logged_user, &form.code, db_pool).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return render_settings(
+                &hb,
+                &logged_user,
+                &cookie_session,
+                &csrf_token,
+                db_pool,
+                Some("This code is not valid."),
+            )
+            .await
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    match TwoFactor::disable(logged_user.id, db_pool).await {
+        Ok(_) => redirect("/account/2fa"),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[post("/account/2fa/recovery_codes")]
+async fn regenerate_recovery_codes(
+    logged_user: LoggedUser,
+    cookie_session: Session,
+    csrf_token: CsrfToken,
+    form: web::Form<CodeRequest>,
+    hb: web::Data<Handlebars<'_>>,
+    db_pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let db_pool = db_pool.get_ref();
+    match confirm_code(&logged_user, &form.code, db_pool).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return render_settings(
+                &hb,
+                &logged_user,
+                &cookie_session,
+                &csrf_token,
+                db_pool,
+                Some("This code is not valid."),
+            )
+            .await
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    match TwoFactor::regenerate_recovery_codes(logged_user.id, db_pool).await {
+        Ok(codes) => render_recovery_codes(&hb, &logged_user, &codes, "/account/2fa"),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Changing the settings needs a current code, so a forgotten open browser isn't enough.
+async fn confirm_code(user: &User, code: &str, db_pool: &DbPool) -> anyhow::Result<bool> {
+    match TwoFactor::find_by_user_id(user.id, db_pool).await? {
+        Some(two_factor) => check_code(&two_factor, code, db_pool).await,
+        None => Ok(false),
+    }
+}
+
+async fn render_settings(
+    hb: &Handlebars<'_>,
+    user: &User,
+    cookie_session: &Session,
+    csrf_token: &CsrfToken,
+    db_pool: &DbPool,
+    error: Option<&str>,
+) -> HttpResponse {
+    let status = if error.is_some() {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::OK
+    };
+    match TwoFactor::find_by_user_id(user.id, db_pool).await {
+        Ok(Some(_)) => {
+            let remaining = TwoFactor::count_recovery_codes(user.id, db_pool)
+                .await
+                .unwrap_or_default();
+            let data = json!({
+                "user": user,
+                "csrf_token": csrf_token,
+                "required": user.role.requires_two_factor(),
+                "remaining_recovery_codes": remaining,
+                "error": error,
+            });
+            let body = hb.render("two_factor_settings", &data).unwrap();
+
+            HttpResponse::build(status).body(body)
+        }
+        Ok(None) => {
+            // keep showing the same secret until it's confirmed
+            let secret = match cookie_session.get::<String>(SETUP_SECRET_KEY) {
+                Ok(Some(s)) => s,
+                _ => {
+                    let secret = totp::generate_secret();
+                    if cookie_session.set(SETUP_SECRET_KEY, &secret).is_err() {
+                        return HttpResponse::InternalServerError().finish();
+                    }
+                    secret
+                }
+            };
+            render_setup(hb, Some(user), csrf_token, user, &secret, error, status)
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// The QR code and the form to confirm it, `logged_user` is None during a forced setup.
+fn render_setup(
+    hb: &Handlebars,
+    logged_user: Option<&User>,
+    csrf_token: &CsrfToken,
+    user: &User,
+    secret: &str,
+    error: Option<&str>,
+    status: StatusCode,
+) -> HttpResponse {
+    let issuer = dotenv::var("APP_NAME").unwrap_or_else(|_| "MiniPress".to_string());
+    let uri = totp::provisioning_uri(secret, &user.username, &issuer);
+    let action = match logged_user {
+        Some(_) => "/account/2fa/enable",
+        None => "/login/2fa/setup",
+    };
+    let data = json!({
+        "user": logged_user,
+        "csrf_token": csrf_token,
+        "action": action,
+        "required": logged_user.is_none(),
+        "qr_code": totp::qr_code_svg(&uri),
+        "secret": secret,
+        "error": error,
+    });
+    let body = hb.render("two_factor_setup", &data).unwrap();
+
+    HttpResponse::build(status).body(body)
+}
+
+fn render_recovery_codes(
+    hb: &Handlebars,
+    user: &User,
+    codes: &[String],
+    continue_to: &str,
+) -> HttpResponse {
+    let data = json!({
+        "user": user,
+        "codes": codes,
+        "continue_to": continue_to,
+    });
+    let body = hb.render("recovery_codes", &data).unwrap();
+
+    HttpResponse::Ok().body(body)
+}
+
+/// Checks a code from the authenticator, or a recovery code. Each one works only once.
+async fn check_code(two_factor: &TwoFactor, code: &str, db_pool: &DbPool) -> anyhow::Result<bool> {
+    match totp::verify(&two_factor.secret, code) {
+        Some(step) => TwoFactor::use_step(two_factor.user_id, step, db_pool).await,
+        None => TwoFactor::use_recovery_code(two_factor.user_id, code, db_pool).await,
+    }
+}
+
+fn redirect(location: &str) -> HttpResponse {
+    HttpResponse::Found()
+        .header(header::LOCATION, location.to_string())
+        .finish()
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(login_page);
+    cfg.service(login);
+    cfg.service(login_setup_page);
+    cfg.service(login_setup);
+    cfg.service(settings);
+    cfg.service(enable);
+    cfg.service(disable);
+    cfg.service(regenerate_recovery_codes);
+}
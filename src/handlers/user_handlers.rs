@@ -1,13 +1,17 @@
 use crate::database::DbPool;
-use crate::models::{User, UserRequest};
+use crate::middleware::{roles, RequireRole};
+use crate::models::{Session, TwoFactor};
+use crate::models::{User, UserDeletion, UserRequest};
+use crate::session::SessionCache;
 use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
 use sqlx::types::Uuid;
 
-// TODO setup proper middleware/route protection for each of the handlers
-
 // TODO pagination
 #[get("/users")]
-async fn find_all(db_pool: web::Data<DbPool>) -> impl Responder {
+async fn find_all(
+    db_pool: web::Data<DbPool>,
+    _logged_user: RequireRole<roles::Admin>,
+) -> impl Responder {
     let result = User::find_all(db_pool.get_ref()).await;
     match result {
         Ok(users) => HttpResponse::Ok().json(users),
@@ -16,7 +20,11 @@ async fn find_all(db_pool: web::Data<DbPool>) -> impl Responder {
 }
 
 #[get("/user/{uuid}")]
-async fn find(uuid: web::Path<String>, db_pool: web::Data<DbPool>) -> impl Responder {
+async fn find(
+    uuid: web::Path<String>,
+    db_pool: web::Data<DbPool>,
+    _logged_user: RequireRole<roles::Admin>,
+) -> impl Responder {
     let uuid_;
     match Uuid::parse_str(uuid.as_str()) {
         Ok(u) => uuid_ = u,
@@ -30,7 +38,14 @@ async fn find(uuid: web::Path<String>, db_pool: web::Data<DbPool>) -> impl Respo
 }
 
 #[post("/user")]
-async fn create(user: web::Json<UserRequest>, db_pool: web::Data<DbPool>) -> impl Responder {
+async fn create(
+    user: web::Json<UserRequest>,
+    db_pool: web::Data<DbPool>,
+    logged_user: RequireRole<roles::Admin>,
+) -> impl Responder {
+    if !logged_user.role.can_assign(user.role) {
+        return HttpResponse::Forbidden().body("Forbidden");
+    }
     let result = User::create(user.into_inner(), db_pool.get_ref()).await;
     match result {
         Ok(user) => HttpResponse::Ok().json(user),
@@ -43,43 +58,197 @@ async fn update(
     uuid: web::Path<String>,
     user: web::Json<UserRequest>,
     db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+    logged_user: RequireRole<roles::Admin>,
 ) -> impl Responder {
     let uuid_;
     match Uuid::parse_str(uuid.as_str()) {
         Ok(u) => uuid_ = u,
         Err(_) => return HttpResponse::BadRequest().body("Invalid User ID"),
     }
-    let result = User::update(uuid_, user.into_inner(), db_pool.get_ref()).await;
+    let existing = match User::find_by_id(uuid_, db_pool.get_ref()).await {
+        Ok(u) => u,
+        Err(_) => return HttpResponse::BadRequest().body("User not found"),
+    };
+    if !logged_user.role.can_manage(existing.role)
+        || (user.role != existing.role && !logged_user.role.can_assign(user.role))
+    {
+        return HttpResponse::Forbidden().body("Forbidden");
+    }
+    let role_changed = user.role != existing.role;
+    let result = match User::update(uuid_, user.into_inner(), db_pool.get_ref()).await {
+        Ok(user) if role_changed => {
+            User::require_two_factor_setup(user.id, user.role, db_pool.get_ref())
+                .await
+                .map(|_| user)
+        }
+        result => result,
+    };
+    cache.remove(uuid_);
     match result {
         Ok(user) => HttpResponse::Ok().json(user),
         _ => HttpResponse::BadRequest().body("User not found"),
     }
 }
 
+/// What happens to the posts of a deleted user, see `UserDeletion`.
+#[derive(Deserialize)]
+pub struct DeleteQuery {
+    /// The id of the user who gets the posts.
+    reassign_to: Option<String>,
+    #[serde(default)]
+    anonymize: bool,
+}
+
 #[delete("/user/{uuid}")]
-async fn delete(uuid: web::Path<String>, db_pool: web::Data<DbPool>) -> impl Responder {
+async fn delete(
+    uuid: web::Path<String>,
+    query: web::Query<DeleteQuery>,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+    logged_user: RequireRole<roles::Admin>,
+) -> impl Responder {
+    let db_pool = db_pool.get_ref();
     let uuid_;
     match Uuid::parse_str(uuid.as_str()) {
         Ok(u) => uuid_ = u,
         Err(_) => return HttpResponse::BadRequest().body("Invalid User ID"),
     }
-    let result = User::delete(uuid_, db_pool.get_ref()).await;
-    match result {
-        Ok(rows) => {
-            if rows > 0 {
-                HttpResponse::Ok().body(format!("Successfully deleted {} record(s)", rows))
-            } else {
-                HttpResponse::BadRequest().body("User not found")
+    let user = match User::find_by_id(uuid_, db_pool).await {
+        Ok(user) if !logged_user.role.can_manage(user.role) => {
+            return HttpResponse::Forbidden().body("Forbidden")
+        }
+        Ok(user) => user,
+        Err(_) => return HttpResponse::BadRequest().body("User not found"),
+    };
+
+    let deletion = match (&query.reassign_to, query.anonymize) {
+        (Some(_), true) => {
+            return HttpResponse::BadRequest().body("Either reassign the posts or anonymize")
+        }
+        (Some(to), false) => {
+            let to = match Uuid::parse_str(to) {
+                Ok(to) => User::find_by_id(to, db_pool).await,
+                Err(_) => return HttpResponse::BadRequest().body("Invalid reassign_to"),
+            };
+            let to = match to {
+                Ok(to) => to,
+                Err(_) => return HttpResponse::BadRequest().body("reassign_to not found"),
+            };
+            match UserDeletion::reassign_to(&user, to) {
+                Ok(deletion) => deletion,
+                Err(error) => return HttpResponse::BadRequest().body(error),
             }
         }
+        (None, true) => UserDeletion::Anonymize,
+        (None, false) => match UserDeletion::count_authored(user.id, db_pool).await {
+            Ok((0, 0)) => UserDeletion::Delete,
+            Ok((posts, reviews)) => {
+                return HttpResponse::Conflict().body(format!(
+                    "The user has {} post(s) and {} review(s), pass reassign_to=<user id> or \
+                        anonymize=true",
+                    posts, reviews
+                ))
+            }
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        },
+    };
+
+    let result = deletion.run(&user, db_pool).await;
+    cache.remove_user_sessions(user.id);
+    match result {
+        Ok(outcome) => {
+            let message = outcome.message(&user.username);
+            log::info!("{}: {}", logged_user.username, message);
+            HttpResponse::Ok().body(message)
+        }
+        _ => HttpResponse::InternalServerError().body("Error trying to delete the user"),
+    }
+}
+
+/// Logs the user out of every browser they're logged in on.
+#[post("/user/{uuid}/sessions/revoke")]
+async fn revoke_sessions(
+    uuid: web::Path<String>,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+    logged_user: RequireRole<roles::Admin>,
+) -> impl Responder {
+    let uuid_;
+    match Uuid::parse_str(uuid.as_str()) {
+        Ok(u) => uuid_ = u,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid User ID"),
+    }
+    let user = match User::find_by_id(uuid_, db_pool.get_ref()).await {
+        Ok(u) => u,
+        Err(_) => return HttpResponse::BadRequest().body("User not found"),
+    };
+    // admins can't log out other admins, only super admins can
+    if !logged_user.role.can_manage(user.role) {
+        return HttpResponse::Forbidden().body("Forbidden");
+    }
+    let result = match Session::delete_by_user_id(user.id, db_pool.get_ref()).await {
+        Ok(_) => User::bump_session_version(user.id, db_pool.get_ref()).await,
+        Err(e) => Err(e),
+    };
+    cache.remove_user_sessions(user.id);
+    match result {
+        Ok(rows) if rows > 0 => HttpResponse::Ok().body("Successfully revoked all sessions"),
         _ => HttpResponse::BadRequest().body("User not found"),
     }
 }
 
+/// Turns off two-factor authentication for a user who lost their device. If their role
+/// requires it they're logged out and have to set it up again when they log in.
+#[post("/user/{uuid}/2fa/reset")]
+async fn reset_two_factor(
+    uuid: web::Path<String>,
+    db_pool: web::Data<DbPool>,
+    cache: web::Data<SessionCache>,
+    logged_user: RequireRole<roles::Admin>,
+) -> impl Responder {
+    let uuid_;
+    match Uuid::parse_str(uuid.as_str()) {
+        Ok(u) => uuid_ = u,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid User ID"),
+    }
+    let user = match User::find_by_id(uuid_, db_pool.get_ref()).await {
+        Ok(u) => u,
+        Err(_) => return HttpResponse::BadRequest().body("User not found"),
+    };
+    // admins can't lower the protection of other admins, only super admins can
+    if !logged_user.role.can_manage(user.role) {
+        return HttpResponse::Forbidden().body("Forbidden");
+    }
+    let result = match TwoFactor::disable(user.id, db_pool.get_ref()).await {
+        Ok(rows) if rows > 0 => {
+            User::require_two_factor_setup(user.id, user.role, db_pool.get_ref())
+                .await
+                .map(|_| rows)
+        }
+        result => result,
+    };
+    cache.remove(user.id);
+    match result {
+        Ok(rows) if rows > 0 => {
+            log::info!(
+                "{} reset the two-factor authentication of {}",
+                logged_user.username,
+                user.username
+            );
+            HttpResponse::Ok().body("Successfully reset two-factor authentication")
+        }
+        Ok(_) => HttpResponse::BadRequest().body("Two-factor authentication is not enabled"),
+        _ => HttpResponse::BadRequest().body("Error trying to reset two-factor authentication"),
+    }
+}
+
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(find_all);
     cfg.service(find);
     cfg.service(create);
     cfg.service(update);
     cfg.service(delete);
+    cfg.service(revoke_sessions);
+    cfg.service(reset_two_factor);
 }
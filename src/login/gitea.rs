@@ -0,0 +1,99 @@
+use crate::login::provider::{
+    authorize_url, exchange_code, request_json, AuthProvider, Profile, ProviderEnv, ProviderToken,
+};
+use crate::login::{LoginError, PendingLogin};
+use futures::future::LocalBoxFuture;
+use oauth2::basic::BasicClient;
+use oauth2::{AuthorizationCode, TokenResponse};
+use url::Url;
+
+/// The parts of Gitea's `/api/v1/user` we use.
+#[derive(Deserialize)]
+struct GiteaUserInfo {
+    id: u64,
+    login: String,
+    full_name: Option<String>,
+    email: Option<String>,
+    avatar_url: Option<String>,
+}
+
+/// A Gitea instance at `GITEA_URL`, e.g. a self-hosted one or Codeberg.
+pub struct GiteaProvider {
+    env: ProviderEnv,
+    oauth: BasicClient,
+    name: String,
+    base_url: String,
+}
+
+impl GiteaProvider {
+    pub fn from_env() -> Option<GiteaProvider> {
+        let env = ProviderEnv::read("GITEA")?;
+        let base_url = dotenv::var("GITEA_URL")
+            .expect("Failed to get the GITEA_URL .env variable")
+            .trim_end_matches('/')
+            .to_string();
+        let name = dotenv::var("GITEA_NAME").unwrap_or_else(|_| "Gitea".to_string());
+        Some(GiteaProvider {
+            oauth: env.client(
+                &format!("{}/login/oauth/authorize", base_url),
+                &format!("{}/login/oauth/access_token", base_url),
+            ),
+            env,
+            name,
+            base_url,
+        })
+    }
+}
+
+impl AuthProvider for GiteaProvider {
+    fn id(&self) -> &str {
+        "gitea"
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn callback_path(&self) -> &str {
+        &self.env.callback_path
+    }
+
+    fn authorize_url<'a>(
+        &'a self,
+        pending: &'a PendingLogin,
+    ) -> LocalBoxFuture<'a, Result<Url, LoginError>> {
+        Box::pin(async move { Ok(authorize_url(&self.oauth, pending, &[])) })
+    }
+
+    fn exchange_code<'a>(
+        &'a self,
+        code: AuthorizationCode,
+        pending: &'a PendingLogin,
+    ) -> LocalBoxFuture<'a, Result<ProviderToken, LoginError>> {
+        Box::pin(async move {
+            let token = exchange_code(&self.oauth, code, pending).await?;
+            Ok(ProviderToken {
+                access_token: token.access_token().secret().to_string(),
+                id_token: None,
+            })
+        })
+    }
+
+    fn fetch_profile<'a>(
+        &'a self,
+        token: &'a ProviderToken,
+        _pending: &'a PendingLogin,
+    ) -> LocalBoxFuture<'a, Result<Profile, LoginError>> {
+        Box::pin(async move {
+            let url = format!("{}/api/v1/user", self.base_url);
+            let info: GiteaUserInfo = request_json(&url, Some(&token.access_token)).await?;
+
+            let mut profile = Profile::new(info.id.to_string(), info.login);
+            profile.user.email = info.email;
+            // Gitea sends an empty string when the name isn't set
+            profile.user.name = info.full_name.filter(|n| !n.is_empty());
+            profile.user.avatar_url = info.avatar_url;
+            Ok(profile)
+        })
+    }
+}
@@ -0,0 +1,342 @@
+use crate::login::provider::{
+    authorize_url, exchange_code, request_json, AuthProvider, Profile, ProviderEnv, ProviderToken,
+};
+use crate::login::{LoginError, PendingLogin};
+use futures::future::LocalBoxFuture;
+use oauth2::basic::BasicClient;
+use oauth2::{AuthorizationCode, TokenResponse};
+use time::PrimitiveDateTime;
+use url::Url;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GithubUserInfo {
+    pub login: String,
+    pub id: u64,
+    pub node_id: String,
+    pub avatar_url: Option<String>,
+    pub gravatar_id: String,
+    pub url: String,
+    pub html_url: String,
+    pub followers_url: String,
+    pub following_url: String,
+    pub gists_url: String,
+    pub starred_url: String,
+    pub subscriptions_url: String,
+    pub organizations_url: String,
+    pub repos_url: String,
+    pub events_url: String,
+    pub received_events_url: String,
+    #[serde(rename = "type")]
+    pub user_type: String,
+    pub site_admin: bool,
+    pub name: Option<String>,
+    pub company: Option<String>,
+    pub blog: Option<String>,
+    pub location: Option<String>,
+    pub email: Option<String>,
+    pub hireable: Option<bool>,
+    pub bio: Option<String>,
+    pub twitter_username: Option<String>,
+    pub public_repos: u32,
+    pub public_gists: u32,
+    pub followers: u64,
+    pub following: u32,
+    #[serde(with = "iso_8601_date_format")]
+    pub created_at: PrimitiveDateTime,
+    #[serde(with = "iso_8601_date_format")]
+    pub updated_at: PrimitiveDateTime,
+}
+
+pub struct GithubProvider {
+    env: ProviderEnv,
+    oauth: BasicClient,
+    api_base_url: String,
+}
+
+impl GithubProvider {
+    pub fn from_env() -> Option<GithubProvider> {
+        let env = ProviderEnv::read("GITHUB")?;
+        let auth_url = dotenv::var("GITHUB_AUTH_URL")
+            .unwrap_or_else(|_| "https://github.com/login/oauth/authorize".to_string());
+        let token_url = dotenv::var("GITHUB_TOKEN_URL")
+            .unwrap_or_else(|_| "https://github.com/login/oauth/access_token".to_string());
+        let api_base_url =
+            dotenv::var("GITHUB_API_URL").unwrap_or_else(|_| "https://api.github.com".to_string());
+        Some(GithubProvider::new(
+            env,
+            &auth_url,
+            &token_url,
+            api_base_url,
+        ))
+    }
+
+    fn new(env: ProviderEnv, auth_url: &str, token_url: &str, api_base_url: String) -> Self {
+        GithubProvider {
+            oauth: env.client(auth_url, token_url),
+            env,
+            api_base_url,
+        }
+    }
+}
+
+impl AuthProvider for GithubProvider {
+    fn id(&self) -> &str {
+        "github"
+    }
+
+    fn name(&self) -> &str {
+        "GitHub"
+    }
+
+    fn callback_path(&self) -> &str {
+        &self.env.callback_path
+    }
+
+    fn authorize_url<'a>(
+        &'a self,
+        pending: &'a PendingLogin,
+    ) -> LocalBoxFuture<'a, Result<Url, LoginError>> {
+        // no scopes, only public information is read
+        Box::pin(async move { Ok(authorize_url(&self.oauth, pending, &[])) })
+    }
+
+    fn exchange_code<'a>(
+        &'a self,
+        code: AuthorizationCode,
+        pending: &'a PendingLogin,
+    ) -> LocalBoxFuture<'a, Result<ProviderToken, LoginError>> {
+        Box::pin(async move {
+            let token = exchange_code(&self.oauth, code, pending).await?;
+            Ok(ProviderToken {
+                access_token: token.access_token().secret().to_string(),
+                id_token: None,
+            })
+        })
+    }
+
+    fn fetch_profile<'a>(
+        &'a self,
+        token: &'a ProviderToken,
+        _pending: &'a PendingLogin,
+    ) -> LocalBoxFuture<'a, Result<Profile, LoginError>> {
+        Box::pin(async move {
+            let url = format!("{}/user", self.api_base_url);
+            let info: GithubUserInfo = request_json(&url, Some(&token.access_token)).await?;
+
+            let mut profile = github_profile(info);
+            profile.user.github_token = Some(token.access_token.clone());
+            Ok(profile)
+        })
+    }
+}
+
+/// The public profile of a GitHub user by their login, for `minipress create-admin` which
+/// sets up an account before its user ever signed in.
+pub async fn find_github_profile(login: &str) -> Result<Profile, LoginError> {
+    let api_base_url =
+        dotenv::var("GITHUB_API_URL").unwrap_or_else(|_| "https://api.github.com".to_string());
+    let url = format!("{}/users/{}", api_base_url, login);
+    let info: GithubUserInfo = request_json(&url, None).await?;
+    Ok(github_profile(info))
+}
+
+fn github_profile(info: GithubUserInfo) -> Profile {
+    let mut profile = Profile::new(info.id.to_string(), info.login);
+    profile.user.email = info.email;
+    profile.user.name = info.name;
+    profile.user.avatar_url = info.avatar_url;
+    profile.user.gravatar_id = Some(info.gravatar_id);
+    profile.user.github_id = Some(info.id as i64);
+    profile
+}
+
+mod iso_8601_date_format {
+    use serde::{self, Deserialize, Deserializer, Serializer};
+    use time::PrimitiveDateTime;
+
+    const FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+    // The signature of a serialize_with function must follow the pattern:
+    //
+    //    fn serialize<S>(&T, S) -> Result<S::Ok, S::Error>
+    //    where
+    //        S: Serializer
+    //
+    // although it may also be generic over the input types T.
+    pub fn serialize<S>(date: &PrimitiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let s = date.format(FORMAT);
+        serializer.serialize_str(&s)
+    }
+
+    // The signature of a deserialize_with function must follow the pattern:
+    //
+    //    fn deserialize<'de, D>(D) -> Result<T, D::Error>
+    //    where
+    //        D: Deserializer<'de>
+    //
+    // although it may also be generic over the output types T.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<PrimitiveDateTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        PrimitiveDateTime::parse(s, FORMAT).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use oauth2::{ClientId, ClientSecret, RedirectUrl};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    const OCTOCAT: &str = r#"{
+        "login": "octocat",
+        "id": 583231,
+        "node_id": "MDQ6VXNlcjU4MzIzMQ==",
+        "avatar_url": "https://avatars.githubusercontent.com/u/583231?v=4",
+        "gravatar_id": "",
+        "url": "https://api.github.com/users/octocat",
+        "html_url": "https://github.com/octocat",
+        "followers_url": "https://api.github.com/users/octocat/followers",
+        "following_url": "https://api.github.com/users/octocat/following{/other_user}",
+        "gists_url": "https://api.github.com/users/octocat/gists{/gist_id}",
+        "starred_url": "https://api.github.com/users/octocat/starred{/owner}{/repo}",
+        "subscriptions_url": "https://api.github.com/users/octocat/subscriptions",
+        "organizations_url": "https://api.github.com/users/octocat/orgs",
+        "repos_url": "https://api.github.com/users/octocat/repos",
+        "events_url": "https://api.github.com/users/octocat/events{/privacy}",
+        "received_events_url": "https://api.github.com/users/octocat/received_events",
+        "type": "User",
+        "site_admin": false,
+        "name": "The Octocat",
+        "company": "@github",
+        "blog": "https://github.blog",
+        "location": "San Francisco",
+        "email": "octocat@github.com",
+        "hireable": null,
+        "bio": null,
+        "twitter_username": null,
+        "public_repos": 8,
+        "public_gists": 8,
+        "followers": 4000,
+        "following": 9,
+        "created_at": "2011-01-25T18:44:36Z",
+        "updated_at": "2021-02-22T12:05:42Z"
+    }"#;
+
+    // Stands in for github.com and api.github.com. `good-code` is exchanged for `token`, which
+    // `/user` answers with `user`.
+    fn github_server(user: &'static str) -> test::TestServer {
+        test::start(move || {
+            App::new()
+                .route(
+                    "/login/oauth/access_token",
+                    web::post().to(|form: web::Form<HashMap<String, String>>| async move {
+                        if form.get("code").map(String::as_str) == Some("good-code") {
+                            HttpResponse::Ok()
+                                .json(json!({ "access_token": "token", "token_type": "bearer" }))
+                        } else {
+                            HttpResponse::Ok().json(json!({
+                                "error": "bad_verification_code",
+                                "error_description": "The code passed is incorrect or expired."
+                            }))
+                        }
+                    }),
+                )
+                .route(
+                    "/user",
+                    web::get().to(move |req: HttpRequest| {
+                        let authorized = req
+                            .headers()
+                            .get("authorization")
+                            .map_or(false, |h| h == "Bearer token");
+                        async move {
+                            if authorized {
+                                HttpResponse::Ok()
+                                    .content_type("application/json")
+                                    .body(user)
+                            } else {
+                                HttpResponse::Unauthorized().finish()
+                            }
+                        }
+                    }),
+                )
+        })
+    }
+
+    fn provider(srv: &test::TestServer) -> GithubProvider {
+        let base_url = format!("http://{}", srv.addr());
+        let env = ProviderEnv {
+            client_id: ClientId::new("client".to_string()),
+            client_secret: ClientSecret::new("secret".to_string()),
+            redirect_url: RedirectUrl::new("https://localhost/auth/github".to_string()).unwrap(),
+            callback_path: "/auth/github".to_string(),
+        };
+        GithubProvider::new(
+            env,
+            &format!("{}/login/oauth/authorize", base_url),
+            &format!("{}/login/oauth/access_token", base_url),
+            base_url,
+        )
+    }
+
+    #[actix_rt::test]
+    async fn signs_in_with_the_github_profile() {
+        let srv = github_server(OCTOCAT);
+        let github = provider(&srv);
+        let pending = PendingLogin::new("github", "/".to_string());
+
+        let code = AuthorizationCode::new("good-code".to_string());
+        let token = github.exchange_code(code, &pending).await.unwrap();
+        assert_eq!(token.access_token, "token");
+        let profile = github.fetch_profile(&token, &pending).await.unwrap();
+        assert_eq!(profile.subject, "583231");
+        assert_eq!(profile.user.username, "octocat");
+        assert_eq!(profile.user.name.as_deref(), Some("The Octocat"));
+        assert_eq!(profile.user.email.as_deref(), Some("octocat@github.com"));
+        assert_eq!(profile.user.github_id, Some(583231));
+        assert_eq!(profile.user.github_token.as_deref(), Some("token"));
+    }
+
+    #[actix_rt::test]
+    async fn rejects_a_bad_code() {
+        let srv = github_server(OCTOCAT);
+        let pending = PendingLogin::new("github", "/".to_string());
+
+        let code = AuthorizationCode::new("expired-code".to_string());
+        let result = provider(&srv).exchange_code(code, &pending).await;
+        assert!(matches!(result, Err(LoginError::InvalidCode)));
+    }
+
+    #[actix_rt::test]
+    async fn reports_a_malformed_profile() {
+        let pending = PendingLogin::new("github", "/".to_string());
+        let token = ProviderToken {
+            access_token: "token".to_string(),
+            id_token: None,
+        };
+        for &user in &[r#"{"login": "octocat"}"#, "<html>Unicorn!</html>"] {
+            let srv = github_server(user);
+            let result = provider(&srv).fetch_profile(&token, &pending).await;
+            assert!(matches!(result, Err(LoginError::MalformedResponse)));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn reports_a_rejected_token() {
+        let srv = github_server(OCTOCAT);
+        let pending = PendingLogin::new("github", "/".to_string());
+        let token = ProviderToken {
+            access_token: "revoked".to_string(),
+            id_token: None,
+        };
+        let result = provider(&srv).fetch_profile(&token, &pending).await;
+        assert!(matches!(result, Err(LoginError::ProviderUnavailable)));
+    }
+}
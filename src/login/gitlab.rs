@@ -0,0 +1,98 @@
+use crate::login::provider::{
+    authorize_url, exchange_code, request_json, AuthProvider, Profile, ProviderEnv, ProviderToken,
+};
+use crate::login::{LoginError, PendingLogin};
+use futures::future::LocalBoxFuture;
+use oauth2::basic::BasicClient;
+use oauth2::{AuthorizationCode, TokenResponse};
+use url::Url;
+
+/// The parts of GitLab's `/api/v4/user` we use.
+#[derive(Deserialize)]
+struct GitlabUserInfo {
+    id: u64,
+    username: String,
+    name: Option<String>,
+    email: Option<String>,
+    avatar_url: Option<String>,
+}
+
+/// gitlab.com or a self-hosted instance at `GITLAB_URL`.
+pub struct GitlabProvider {
+    env: ProviderEnv,
+    oauth: BasicClient,
+    name: String,
+    base_url: String,
+}
+
+impl GitlabProvider {
+    pub fn from_env() -> Option<GitlabProvider> {
+        let env = ProviderEnv::read("GITLAB")?;
+        let base_url = dotenv::var("GITLAB_URL")
+            .unwrap_or_else(|_| "https://gitlab.com".to_string())
+            .trim_end_matches('/')
+            .to_string();
+        let name = dotenv::var("GITLAB_NAME").unwrap_or_else(|_| "GitLab".to_string());
+        Some(GitlabProvider {
+            oauth: env.client(
+                &format!("{}/oauth/authorize", base_url),
+                &format!("{}/oauth/token", base_url),
+            ),
+            env,
+            name,
+            base_url,
+        })
+    }
+}
+
+impl AuthProvider for GitlabProvider {
+    fn id(&self) -> &str {
+        "gitlab"
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn callback_path(&self) -> &str {
+        &self.env.callback_path
+    }
+
+    fn authorize_url<'a>(
+        &'a self,
+        pending: &'a PendingLogin,
+    ) -> LocalBoxFuture<'a, Result<Url, LoginError>> {
+        Box::pin(async move { Ok(authorize_url(&self.oauth, pending, &["read_user"])) })
+    }
+
+    fn exchange_code<'a>(
+        &'a self,
+        code: AuthorizationCode,
+        pending: &'a PendingLogin,
+    ) -> LocalBoxFuture<'a, Result<ProviderToken, LoginError>> {
+        Box::pin(async move {
+            let token = exchange_code(&self.oauth, code, pending).await?;
+            Ok(ProviderToken {
+                access_token: token.access_token().secret().to_string(),
+                id_token: None,
+            })
+        })
+    }
+
+    fn fetch_profile<'a>(
+        &'a self,
+        token: &'a ProviderToken,
+        _pending: &'a PendingLogin,
+    ) -> LocalBoxFuture<'a, Result<Profile, LoginError>> {
+        Box::pin(async move {
+            let url = format!("{}/api/v4/user", self.base_url);
+            let info: GitlabUserInfo = request_json(&url, Some(&token.access_token)).await?;
+
+            let mut profile = Profile::new(info.id.to_string(), info.username);
+            profile.user.email = info.email;
+            profile.user.name = info.name;
+            profile.user.avatar_url = info.avatar_url;
+            Ok(profile)
+        })
+    }
+}
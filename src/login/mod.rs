@@ -0,0 +1,263 @@
+mod gitea;
+mod github;
+mod gitlab;
+mod oidc;
+mod provider;
+mod sync;
+pub mod two_factor;
+
+use crate::middleware::{render_error, render_suspended};
+use crate::models::Suspension;
+use actix_session::Session;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use oauth2::{CsrfToken, PkceCodeChallenge};
+
+pub use github::find_github_profile;
+pub use provider::{AuthProvider, AuthProviders, Profile, ProviderToken};
+pub use sync::sync_profile;
+
+/// Session key of the `PendingLogin` while the user is at the identity provider. Starting a new
+/// login, with any provider, replaces the previous one.
+const PENDING_LOGIN_KEY: &str = "pending_login";
+
+/// Secrets generated when a login starts that are checked when the provider redirects back.
+#[derive(Serialize, Deserialize)]
+pub struct PendingLogin {
+    /// The provider the login was started with, e.g. `github`.
+    pub provider: String,
+    /// The `state` param the provider has to send back to the callback.
+    pub csrf_state: String,
+    /// Sent along with the code, proves this is the client that started the login.
+    pub pkce_verifier: String,
+    /// OpenID Connect providers have to put it in the id token, it prevents replaying a token.
+    pub nonce: String,
+    /// Where to send the user after logging in, already validated with `safe_return_to`.
+    pub return_to: String,
+}
+
+impl PendingLogin {
+    /// Generates the secrets for a new login with `provider`.
+    pub fn new(provider: &str, return_to: String) -> PendingLogin {
+        let (_, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        PendingLogin {
+            provider: provider.to_string(),
+            csrf_state: CsrfToken::new_random().secret().to_string(),
+            pkce_verifier: pkce_verifier.secret().to_string(),
+            nonce: CsrfToken::new_random().secret().to_string(),
+            return_to,
+        }
+    }
+
+    pub fn save(&self, session: &Session) -> Result<(), actix_web::Error> {
+        session.set(PENDING_LOGIN_KEY, self)
+    }
+
+    /// Removes the pending login from the session, each one can only be used once.
+    pub fn take(session: &Session, provider: &str) -> Result<PendingLogin, LoginError> {
+        let pending = session.get::<PendingLogin>(PENDING_LOGIN_KEY);
+        session.remove(PENDING_LOGIN_KEY);
+        match pending {
+            Ok(Some(p)) if p.provider == provider => Ok(p),
+            _ => Err(LoginError::NoPendingLogin),
+        }
+    }
+
+    /// Compares the returned state in constant time.
+    pub fn state_matches(&self, state: &str) -> bool {
+        let expected = self.csrf_state.as_bytes();
+        let state = state.as_bytes();
+        expected.len() == state.len()
+            && expected
+                .iter()
+                .zip(state.iter())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+/// The params a provider redirects back with. On success there's a `code`, if the user denied
+/// access or something else went wrong there's an `error` instead.
+#[derive(Deserialize)]
+pub struct AuthRequest {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+impl AuthRequest {
+    /// Checks the state against the pending login and returns the authorization code.
+    pub fn verify(self, pending: &PendingLogin) -> Result<String, LoginError> {
+        match self.state {
+            Some(state) if pending.state_matches(&state) => {}
+            _ => return Err(LoginError::StateMismatch),
+        }
+        match (self.code, self.error) {
+            (_, Some(error)) if error == "access_denied" => Err(LoginError::Denied),
+            (Some(code), None) => Ok(code),
+            _ => Err(LoginError::InvalidCode),
+        }
+    }
+}
+
+/// Everything that can go wrong in a login callback, each one is shown on the error page.
+#[derive(Debug)]
+pub enum LoginError {
+    /// There's no pending login in the cookie session, it expired or was used already.
+    NoPendingLogin,
+    /// The `state` the provider sent back doesn't match the one we generated.
+    StateMismatch,
+    /// The user didn't authorize the app.
+    Denied,
+    /// The provider didn't accept the code, e.g. it was used already or expired.
+    InvalidCode,
+    /// The provider couldn't be reached or answered with an error.
+    ProviderUnavailable,
+    /// The provider answered with something we couldn't parse.
+    MalformedResponse,
+    /// The id token's signature, issuer, audience, expiry or nonce didn't check out.
+    InvalidIdToken,
+    /// The user is suspended by an admin.
+    Suspended(Suspension),
+    /// Finding, creating or logging in the user failed.
+    Database,
+}
+
+impl LoginError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            LoginError::ProviderUnavailable | LoginError::MalformedResponse => {
+                StatusCode::BAD_GATEWAY
+            }
+            LoginError::Suspended(_) => StatusCode::FORBIDDEN,
+            LoginError::Database => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// The message shown to the user, `provider` is the display name, e.g. "GitHub".
+    pub fn message(&self, provider: &str) -> String {
+        match self {
+            LoginError::NoPendingLogin => {
+                "This login has expired or was started in another browser. Please sign in again."
+                    .to_string()
+            }
+            LoginError::StateMismatch | LoginError::InvalidIdToken => {
+                "The login could not be verified. Please sign in again.".to_string()
+            }
+            LoginError::Denied => {
+                format!("You need to authorize the app on {} to sign in.", provider)
+            }
+            LoginError::InvalidCode => {
+                format!(
+                    "{} did not accept the login. Please sign in again.",
+                    provider
+                )
+            }
+            LoginError::ProviderUnavailable => format!(
+                "{} could not be reached. Please try again in a few minutes.",
+                provider
+            ),
+            LoginError::MalformedResponse => format!(
+                "{} sent an unexpected response. Please try again.",
+                provider
+            ),
+            LoginError::Suspended(_) => "This account is suspended.".to_string(),
+            LoginError::Database => {
+                "Your account could not be loaded. Please try again.".to_string()
+            }
+        }
+    }
+
+    /// Renders the error page for a failed login with `provider`.
+    pub fn error_page(&self, req: &HttpRequest, provider: &str) -> HttpResponse {
+        log::warn!("{} login failed: {:?}", provider, self);
+        if let LoginError::Suspended(suspension) = self {
+            return render_suspended(req, suspension);
+        }
+        render_error(
+            req,
+            self.status(),
+            &self.message(provider),
+            "Sign in failed",
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuthRequest, LoginError, PendingLogin};
+    use actix_session::UserSession;
+    use actix_web::test::TestRequest;
+
+    fn callback(code: Option<&str>, state: Option<&str>, error: Option<&str>) -> AuthRequest {
+        AuthRequest {
+            code: code.map(String::from),
+            state: state.map(String::from),
+            error: error.map(String::from),
+        }
+    }
+
+    #[test]
+    fn accepts_the_code_with_the_state_of_the_pending_login() {
+        let pending = PendingLogin::new("github", "/".to_string());
+        let state = pending.csrf_state.clone();
+        let code = callback(Some("code"), Some(&state), None).verify(&pending);
+        assert_eq!(code.unwrap(), "code");
+    }
+
+    #[test]
+    fn rejects_a_state_that_does_not_match() {
+        let pending = PendingLogin::new("github", "/".to_string());
+        let other = PendingLogin::new("github", "/".to_string());
+        let result = callback(Some("code"), Some(&other.csrf_state), None).verify(&pending);
+        assert!(matches!(result, Err(LoginError::StateMismatch)));
+        let result = callback(Some("code"), Some(""), None).verify(&pending);
+        assert!(matches!(result, Err(LoginError::StateMismatch)));
+        let result = callback(Some("code"), None, None).verify(&pending);
+        assert!(matches!(result, Err(LoginError::StateMismatch)));
+    }
+
+    #[test]
+    fn reports_denied_consent() {
+        let pending = PendingLogin::new("github", "/".to_string());
+        let state = pending.csrf_state.clone();
+        let result = callback(None, Some(&state), Some("access_denied")).verify(&pending);
+        assert!(matches!(result, Err(LoginError::Denied)));
+        // the state is checked first, anyone could send an error
+        let result = callback(None, Some("forged"), Some("access_denied")).verify(&pending);
+        assert!(matches!(result, Err(LoginError::StateMismatch)));
+    }
+
+    #[test]
+    fn rejects_a_callback_without_a_code() {
+        let pending = PendingLogin::new("github", "/".to_string());
+        let state = pending.csrf_state.clone();
+        let result = callback(None, Some(&state), None).verify(&pending);
+        assert!(matches!(result, Err(LoginError::InvalidCode)));
+        let result = callback(None, Some(&state), Some("server_error")).verify(&pending);
+        assert!(matches!(result, Err(LoginError::InvalidCode)));
+    }
+
+    #[test]
+    fn pending_logins_can_only_be_used_once_by_their_provider() {
+        let session = TestRequest::default().to_http_request().get_session();
+        PendingLogin::new("github", "/".to_string())
+            .save(&session)
+            .unwrap();
+        assert!(PendingLogin::take(&session, "github").is_ok());
+        assert!(matches!(
+            PendingLogin::take(&session, "github"),
+            Err(LoginError::NoPendingLogin)
+        ));
+
+        PendingLogin::new("github", "/".to_string())
+            .save(&session)
+            .unwrap();
+        assert!(matches!(
+            PendingLogin::take(&session, "gitlab"),
+            Err(LoginError::NoPendingLogin)
+        ));
+    }
+}
@@ -0,0 +1,377 @@
+use crate::login::gitea::GiteaProvider;
+use crate::login::github::GithubProvider;
+use crate::login::gitlab::GitlabProvider;
+use crate::login::oidc::OidcProvider;
+use crate::login::{LoginError, PendingLogin};
+use crate::models::{user::Role, UserRequest};
+use actix_http::http::Uri;
+use futures::future::LocalBoxFuture;
+use oauth2::basic::{BasicClient, BasicErrorResponse, BasicErrorResponseType};
+use oauth2::http::header::{ACCEPT, AUTHORIZATION, USER_AGENT};
+use oauth2::http::{HeaderMap, HeaderValue, Method};
+use oauth2::reqwest::async_http_client;
+use oauth2::{
+    AuthUrl, AuthorizationCode, Client, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, RequestTokenError, Scope, TokenResponse, TokenType, TokenUrl,
+};
+use serde::de::DeserializeOwned;
+use std::time::SystemTime;
+use time::PrimitiveDateTime;
+use url::Url;
+
+/// A service users can sign in with. GitHub, GitLab and Gitea use plain OAuth2, any other
+/// OpenID Connect provider is supported by `OidcProvider`.
+pub trait AuthProvider {
+    /// Used in the login url and stored in `user_identities`, e.g. `github`.
+    fn id(&self) -> &str;
+
+    /// Shown on the login page and in errors, e.g. "GitHub".
+    fn name(&self) -> &str;
+
+    /// The path of the callback url the provider redirects back to.
+    fn callback_path(&self) -> &str;
+
+    /// The url to send the user to, with the state, PKCE challenge and nonce of `pending`.
+    fn authorize_url<'a>(
+        &'a self,
+        pending: &'a PendingLogin,
+    ) -> LocalBoxFuture<'a, Result<Url, LoginError>>;
+
+    /// Exchanges the code the provider redirected back with for a token.
+    fn exchange_code<'a>(
+        &'a self,
+        code: AuthorizationCode,
+        pending: &'a PendingLogin,
+    ) -> LocalBoxFuture<'a, Result<ProviderToken, LoginError>>;
+
+    /// Fetches the account the token belongs to.
+    fn fetch_profile<'a>(
+        &'a self,
+        token: &'a ProviderToken,
+        pending: &'a PendingLogin,
+    ) -> LocalBoxFuture<'a, Result<Profile, LoginError>>;
+}
+
+pub struct ProviderToken {
+    pub access_token: String,
+    /// Only OpenID Connect providers send an id token.
+    pub id_token: Option<String>,
+}
+
+/// An account at a provider.
+pub struct Profile {
+    /// The id of the account at the provider, it doesn't change when the account is renamed.
+    pub subject: String,
+    /// The user to create when the account signs in for the first time.
+    pub user: UserRequest,
+}
+
+impl Profile {
+    /// A profile with only a username, providers fill in whatever else they know.
+    pub fn new(subject: String, username: String) -> Profile {
+        Profile {
+            subject,
+            user: UserRequest {
+                username,
+                email: None,
+                password: None,
+                name: None,
+                avatar_url: None,
+                gravatar_id: None,
+                github_id: None,
+                github_token: None,
+                role: Role::Subscriber,
+                created_at: PrimitiveDateTime::from(SystemTime::now()),
+                updated_at: PrimitiveDateTime::from(SystemTime::now()),
+            },
+        }
+    }
+}
+
+/// The enabled providers, in the order they are listed on the login page.
+pub struct AuthProviders(Vec<Box<dyn AuthProvider>>);
+
+impl AuthProviders {
+    /// A provider is enabled when its client id is set in the .env.
+    pub fn from_env() -> AuthProviders {
+        let mut providers: Vec<Box<dyn AuthProvider>> = Vec::new();
+        if let Some(p) = GithubProvider::from_env() {
+            providers.push(Box::new(p));
+        }
+        if let Some(p) = GitlabProvider::from_env() {
+            providers.push(Box::new(p));
+        }
+        if let Some(p) = GiteaProvider::from_env() {
+            providers.push(Box::new(p));
+        }
+        if let Some(p) = OidcProvider::from_env() {
+            providers.push(Box::new(p));
+        }
+        AuthProviders(providers)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn AuthProvider> {
+        self.0.iter().map(|p| p.as_ref())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&dyn AuthProvider> {
+        self.iter().find(|p| p.id() == id)
+    }
+
+    pub fn by_callback_path(&self, path: &str) -> Option<&dyn AuthProvider> {
+        self.iter().find(|p| p.callback_path() == path)
+    }
+}
+
+/// The `{PREFIX}_CLIENT_ID`, `{PREFIX}_CLIENT_SECRET` and `{PREFIX}_CALLBACK_URL` of a provider.
+pub struct ProviderEnv {
+    pub client_id: ClientId,
+    pub client_secret: ClientSecret,
+    pub redirect_url: RedirectUrl,
+    pub callback_path: String,
+}
+
+impl ProviderEnv {
+    /// Returns None if the client id isn't set, the provider is disabled then.
+    pub fn read(prefix: &str) -> Option<ProviderEnv> {
+        let client_id = match dotenv::var(format!("{}_CLIENT_ID", prefix)) {
+            Ok(id) if !id.is_empty() => ClientId::new(id),
+            _ => return None,
+        };
+        let client_secret = ClientSecret::new(
+            dotenv::var(format!("{}_CLIENT_SECRET", prefix)).unwrap_or_else(|_| {
+                panic!("Failed to get the {}_CLIENT_SECRET .env variable.", prefix)
+            }),
+        );
+
+        // Basic validation of callback url. Url needs to start with a slash and have at least one character after.
+        let callback_url = dotenv::var(format!("{}_CALLBACK_URL", prefix))
+            .unwrap_or_else(|_| panic!("Failed to get the {}_CALLBACK_URL .env variable", prefix))
+            .parse::<Uri>()
+            .unwrap_or_else(|_| panic!("Invalid {}_CALLBACK_URL", prefix));
+        if callback_url.path().len() <= 2 {
+            panic!("Callback url not valid. Path is not long enough.");
+        }
+
+        Some(ProviderEnv {
+            client_id,
+            client_secret,
+            redirect_url: RedirectUrl::new(callback_url.to_string()).expect("Invalid callback URL"),
+            callback_path: callback_url.path().to_string(),
+        })
+    }
+
+    /// Set up the config for the OAuth2 process.
+    pub fn client(&self, auth_url: &str, token_url: &str) -> BasicClient {
+        BasicClient::new(
+            self.client_id.clone(),
+            Some(self.client_secret.clone()),
+            AuthUrl::new(auth_url.to_string()).expect("Invalid authorization endpoint URL"),
+            Some(TokenUrl::new(token_url.to_string()).expect("Invalid token endpoint URL")),
+        )
+        .set_redirect_url(self.redirect_url.clone())
+    }
+}
+
+/// The authorization url of a plain OAuth2 provider.
+pub fn authorize_url<TR, TT>(
+    client: &Client<BasicErrorResponse, TR, TT>,
+    pending: &PendingLogin,
+    scopes: &[&str],
+) -> Url
+where
+    TR: TokenResponse<TT>,
+    TT: TokenType,
+{
+    let pkce_verifier = PkceCodeVerifier::new(pending.pkce_verifier.clone());
+    let mut request = client
+        .authorize_url(|| CsrfToken::new(pending.csrf_state.clone()))
+        .set_pkce_challenge(PkceCodeChallenge::from_code_verifier_sha256(&pkce_verifier));
+    for scope in scopes {
+        request = request.add_scope(Scope::new(scope.to_string()));
+    }
+    request.url().0
+}
+
+/// Exchanges the code for a token, sending the PKCE verifier of `pending` along.
+pub async fn exchange_code<TR, TT>(
+    client: &Client<BasicErrorResponse, TR, TT>,
+    code: AuthorizationCode,
+    pending: &PendingLogin,
+) -> Result<TR, LoginError>
+where
+    TR: TokenResponse<TT>,
+    TT: TokenType,
+{
+    client
+        .exchange_code(code)
+        .set_pkce_verifier(PkceCodeVerifier::new(pending.pkce_verifier.clone()))
+        .request_async(async_http_client)
+        .await
+        .map_err(|e| match e {
+            RequestTokenError::ServerResponse(r)
+                if *r.error() == BasicErrorResponseType::InvalidGrant =>
+            {
+                LoginError::InvalidCode
+            }
+            RequestTokenError::ServerResponse(_) | RequestTokenError::Request(_) => {
+                LoginError::ProviderUnavailable
+            }
+            // GitHub answers a bad code with a 200 and an error instead of a token
+            RequestTokenError::Parse(_, body) if is_bad_code(&body) => LoginError::InvalidCode,
+            _ => LoginError::MalformedResponse,
+        })
+}
+
+// Whether a response that isn't a token is GitHub's error for a wrong or expired code.
+fn is_bad_code(body: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .map_or(false, |error| error["error"] == "bad_verification_code")
+}
+
+/// GETs a url from the provider and parses the JSON response. `access_token` is sent as a
+/// bearer token for the api endpoints.
+pub async fn request_json<T: DeserializeOwned>(
+    url: &str,
+    access_token: Option<&str>,
+) -> Result<T, LoginError> {
+    let url = Url::parse(url).map_err(|_| LoginError::ProviderUnavailable)?;
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+    // the GitHub api rejects requests without a user agent
+    headers.insert(USER_AGENT, HeaderValue::from_static("minipress"));
+    if let Some(token) = access_token {
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(|_| LoginError::MalformedResponse)?,
+        );
+    }
+    let resp = async_http_client(oauth2::HttpRequest {
+        url,
+        method: Method::GET,
+        headers,
+        body: Vec::new(),
+    })
+    .await
+    .map_err(|_| LoginError::ProviderUnavailable)?;
+    if !resp.status_code.is_success() {
+        return Err(LoginError::ProviderUnavailable);
+    }
+    serde_json::from_slice(&resp.body).map_err(|_| LoginError::MalformedResponse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    // A token endpoint that only accepts `good-code` along with the PKCE verifier `verifier`,
+    // and one that answers with something that isn't a token.
+    fn oauth_server(verifier: String) -> test::TestServer {
+        test::start(move || {
+            let verifier = verifier.clone();
+            App::new()
+                .route(
+                    "/token",
+                    web::post().to(move |form: web::Form<HashMap<String, String>>| {
+                        let accepted = form.get("grant_type").map(String::as_str)
+                            == Some("authorization_code")
+                            && form.get("code").map(String::as_str) == Some("good-code")
+                            && form.get("code_verifier") == Some(&verifier);
+                        async move {
+                            if accepted {
+                                HttpResponse::Ok().json(
+                                    json!({ "access_token": "token", "token_type": "bearer" }),
+                                )
+                            } else {
+                                HttpResponse::BadRequest().json(json!({ "error": "invalid_grant" }))
+                            }
+                        }
+                    }),
+                )
+                .route(
+                    "/broken",
+                    web::post().to(|| async {
+                        HttpResponse::Ok()
+                            .content_type("application/json")
+                            .body("{\"access_token\":")
+                    }),
+                )
+        })
+    }
+
+    fn client(token_url: &str) -> BasicClient {
+        ProviderEnv {
+            client_id: ClientId::new("client".to_string()),
+            client_secret: ClientSecret::new("secret".to_string()),
+            redirect_url: RedirectUrl::new("https://localhost/auth/test".to_string()).unwrap(),
+            callback_path: "/auth/test".to_string(),
+        }
+        .client("https://localhost/authorize", token_url)
+    }
+
+    fn url(srv: &test::TestServer, path: &str) -> String {
+        format!("http://{}{}", srv.addr(), path)
+    }
+
+    #[test]
+    fn authorize_url_sends_the_state_and_pkce_challenge() {
+        let pending = PendingLogin::new("test", "/".to_string());
+        let url = authorize_url(&client("https://localhost/token"), &pending, &["read"]);
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        let verifier = PkceCodeVerifier::new(pending.pkce_verifier.clone());
+        let challenge = PkceCodeChallenge::from_code_verifier_sha256(&verifier);
+
+        assert_eq!(params["state"], pending.csrf_state);
+        assert_eq!(params["code_challenge"], challenge.as_str());
+        assert_eq!(params["code_challenge_method"], "S256");
+        assert_eq!(params["scope"], "read");
+        assert!(!url.as_str().contains(&pending.pkce_verifier));
+    }
+
+    #[actix_rt::test]
+    async fn exchange_code_sends_the_pkce_verifier() {
+        let pending = PendingLogin::new("test", "/".to_string());
+        let srv = oauth_server(pending.pkce_verifier.clone());
+        let client = client(&url(&srv, "/token"));
+
+        let code = AuthorizationCode::new("good-code".to_string());
+        let token = exchange_code(&client, code, &pending).await.unwrap();
+        assert_eq!(token.access_token().secret(), "token");
+
+        // the code is only accepted along with the verifier of the login that got it
+        let other = PendingLogin::new("test", "/".to_string());
+        let code = AuthorizationCode::new("good-code".to_string());
+        let result = exchange_code(&client, code, &other).await;
+        assert!(matches!(result, Err(LoginError::InvalidCode)));
+    }
+
+    #[actix_rt::test]
+    async fn exchange_code_rejects_a_bad_code() {
+        let pending = PendingLogin::new("test", "/".to_string());
+        let srv = oauth_server(pending.pkce_verifier.clone());
+        let client = client(&url(&srv, "/token"));
+
+        let code = AuthorizationCode::new("used-code".to_string());
+        let result = exchange_code(&client, code, &pending).await;
+        assert!(matches!(result, Err(LoginError::InvalidCode)));
+    }
+
+    #[actix_rt::test]
+    async fn exchange_code_reports_a_malformed_or_missing_token_endpoint() {
+        let pending = PendingLogin::new("test", "/".to_string());
+        let srv = oauth_server(pending.pkce_verifier.clone());
+
+        let code = AuthorizationCode::new("good-code".to_string());
+        let result = exchange_code(&client(&url(&srv, "/broken")), code, &pending).await;
+        assert!(matches!(result, Err(LoginError::MalformedResponse)));
+
+        let unreachable = format!("http://{}/token", test::unused_addr());
+        let code = AuthorizationCode::new("good-code".to_string());
+        let result = exchange_code(&client(&unreachable), code, &pending).await;
+        assert!(matches!(result, Err(LoginError::ProviderUnavailable)));
+    }
+}
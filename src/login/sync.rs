@@ -0,0 +1,97 @@
+use crate::database::DbPool;
+use crate::login::Profile;
+use crate::models::user::is_valid_username;
+use crate::models::{User, UserIdentity, UserRequest};
+use anyhow::Result;
+
+/// Brings the user of `identity` up to date with the profile the provider just sent, on every
+/// login. The token always comes from the provider. The username, name, email and avatar only
+/// follow the provider while they still have the value it sent last time, so the ones the user
+/// changed here are kept. Before the first sync is recorded only the empty ones are filled in.
+pub async fn sync_profile(
+    identity: &UserIdentity,
+    profile: &Profile,
+    pool: &DbPool,
+) -> Result<User> {
+    let user = User::find_by_id(identity.user_id, pool).await?;
+    let synced = identity.synced_at.is_some();
+    let new = &profile.user;
+
+    let renamed = synced && new.username != user.username;
+    let username = if renamed && identity.username.as_deref() == Some(user.username.as_str()) {
+        rename(&user.username, &new.username, pool).await
+    } else {
+        user.username.clone()
+    };
+    let request = UserRequest {
+        username,
+        email: follow(synced, &user.email, &identity.email, &new.email),
+        password: None,
+        name: follow(synced, &user.name, &identity.name, &new.name),
+        avatar_url: follow(
+            synced,
+            &user.avatar_url,
+            &identity.avatar_url,
+            &new.avatar_url,
+        ),
+        gravatar_id: new.gravatar_id.clone().or_else(|| user.gravatar_id.clone()),
+        github_id: user.github_id,
+        github_token: new
+            .github_token
+            .clone()
+            .or_else(|| user.github_token.clone()),
+        role: user.role,
+        created_at: user.created_at,
+        updated_at: user.updated_at,
+    };
+
+    let changed = request.username != user.username
+        || request.email != user.email
+        || request.name != user.name
+        || request.avatar_url != user.avatar_url
+        || request.gravatar_id != user.gravatar_id
+        || request.github_token != user.github_token;
+    let user = if changed {
+        User::update_profile(user.id, &request, pool).await?
+    } else {
+        user
+    };
+    UserIdentity::record_sync(identity.id, new, pool).await?;
+
+    Ok(user)
+}
+
+// The value a field gets: the provider's while the user didn't change what it sent last time, or
+// while it's empty before anything was recorded. A value the provider stopped sending, like an
+// email made private, is kept.
+fn follow(
+    synced: bool,
+    current: &Option<String>,
+    last: &Option<String>,
+    new: &Option<String>,
+) -> Option<String> {
+    let follows = if synced {
+        current == last
+    } else {
+        current.is_none()
+    };
+    match new {
+        Some(_) if follows => new.clone(),
+        _ => current.clone(),
+    }
+}
+
+// The account was renamed at the provider and the user still has its old name here. They get the
+// new name too, unless someone else already has it, so their author page moves along.
+async fn rename(old: &str, new: &str, pool: &DbPool) -> String {
+    if !is_valid_username(new) || User::find_by_username(new, pool).await.is_ok() {
+        log::warn!(
+            "@{} was renamed to {} at their login provider, but that username can't be used",
+            old,
+            new
+        );
+        return old.to_string();
+    }
+    log::info!("@{} was renamed to @{} at their login provider", old, new);
+    new.to_string()
+}
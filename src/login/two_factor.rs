@@ -0,0 +1,100 @@
+use crate::database::DbPool;
+use crate::login::LoginError;
+use crate::models::uuid_serializer;
+use crate::models::{Suspension, TwoFactor, User};
+use crate::session;
+use actix_identity::Identity;
+use actix_session::Session;
+use actix_web::HttpRequest;
+use sqlx::types::Uuid;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Session key of the `PendingTwoFactor` between the first and the second factor.
+const PENDING_TWO_FACTOR_KEY: &str = "pending_two_factor";
+
+/// How long the user has to enter the code after signing in with a provider or password.
+const PENDING_TWO_FACTOR_SECONDS: u64 = 10 * 60;
+
+/// A user who passed the first factor but still has to enter a code, or set up two-factor
+/// authentication first because their role requires it. Their identity isn't remembered yet.
+#[derive(Serialize, Deserialize)]
+pub struct PendingTwoFactor {
+    #[serde(with = "uuid_serializer")]
+    pub user_id: Uuid,
+    /// Has to still match when the code is entered, so a revoke in between isn't undone.
+    pub session_version: i32,
+    pub return_to: String,
+    expires_at: u64,
+    /// The secret shown during a forced setup, saved once the user confirmed it with a code.
+    pub setup_secret: Option<String>,
+}
+
+impl PendingTwoFactor {
+    pub fn get(session: &Session) -> Option<PendingTwoFactor> {
+        session
+            .get::<PendingTwoFactor>(PENDING_TWO_FACTOR_KEY)
+            .ok()
+            .flatten()
+            .filter(|p| p.expires_at > now())
+    }
+
+    pub fn save(&self, session: &Session) -> Result<(), actix_web::Error> {
+        session.set(PENDING_TWO_FACTOR_KEY, self)
+    }
+
+    pub fn remove(session: &Session) {
+        session.remove(PENDING_TWO_FACTOR_KEY);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Called once the user passed the first factor. Logs them in right away if they don't need a
+/// second factor, otherwise they're sent on to enter their code or to set up two-factor
+/// authentication. Returns where to redirect to. Suspended users are turned away here, before
+/// they're asked for a second factor.
+pub async fn begin(
+    req: &HttpRequest,
+    id: &Identity,
+    cookie_session: &Session,
+    user: &User,
+    return_to: String,
+    pool: &DbPool,
+) -> Result<String, LoginError> {
+    match Suspension::find_by_user_id(user.id, pool).await {
+        Ok(None) => {}
+        Ok(Some(suspension)) => return Err(LoginError::Suspended(suspension)),
+        Err(_) => return Err(LoginError::Database),
+    }
+    let enabled = TwoFactor::find_by_user_id(user.id, pool)
+        .await
+        .map_err(|_| LoginError::Database)?
+        .is_some();
+    if !enabled && !user.role.requires_two_factor() {
+        session::login(req, id, user, pool)
+            .await
+            .map_err(|_| LoginError::Database)?;
+        return Ok(return_to);
+    }
+
+    PendingTwoFactor {
+        user_id: user.id,
+        session_version: user.session_version,
+        return_to,
+        expires_at: now() + PENDING_TWO_FACTOR_SECONDS,
+        setup_secret: None,
+    }
+    .save(cookie_session)
+    .map_err(|_| LoginError::Database)?;
+
+    if enabled {
+        Ok("/login/2fa".to_string())
+    } else {
+        Ok("/login/2fa/setup".to_string())
+    }
+}
@@ -0,0 +1,213 @@
+use actix_web::web;
+use anyhow::{anyhow, Result};
+use handlebars::Handlebars;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::Serialize;
+
+/// Sends the emails rendered from the templates in `resources/templates/emails`. Without
+/// `SMTP_HOST` only the recipient and subject are logged, the body too with `MAIL_LOG_BODIES`.
+pub struct Mailer {
+    transport: Option<SmtpTransport>,
+    from: Mailbox,
+    /// The bodies hold reset and verification links, they're only logged in development.
+    log_bodies: bool,
+    /// Links in emails are absolute, e.g. `https://example.com`.
+    pub base_url: String,
+}
+
+impl Mailer {
+    pub fn from_env() -> Mailer {
+        let from = dotenv::var("MAIL_FROM")
+            .unwrap_or_else(|_| "MiniPress <noreply@localhost>".to_string())
+            .parse()
+            .expect("Invalid MAIL_FROM");
+        let base_url = dotenv::var("APP_BASE_URL")
+            .unwrap_or_else(|_| "https://localhost:4343".to_string())
+            .trim_end_matches('/')
+            .to_string();
+        let log_bodies = dotenv::var("MAIL_LOG_BODIES").map_or(false, |v| v == "true");
+        let host = match dotenv::var("SMTP_HOST") {
+            Ok(host) if !host.is_empty() => host,
+            _ => {
+                log::warn!("SMTP_HOST is not set, emails will only be logged");
+                if log_bodies {
+                    log::warn!("MAIL_LOG_BODIES is set, the logs will contain reset links");
+                }
+                return Mailer {
+                    transport: None,
+                    from,
+                    log_bodies,
+                    base_url,
+                };
+            }
+        };
+
+        // `none` is for local sinks like MailHog, `tls` connects with TLS right away and
+        // `starttls` upgrades the connection
+        let tls = dotenv::var("SMTP_TLS").unwrap_or_else(|_| "starttls".to_string());
+        let mut builder = match tls.as_str() {
+            "none" => SmtpTransport::builder_dangerous(&host),
+            "tls" => SmtpTransport::relay(&host).expect("Invalid SMTP_HOST"),
+            _ => SmtpTransport::starttls_relay(&host).expect("Invalid SMTP_HOST"),
+        };
+        if let Ok(port) = dotenv::var("SMTP_PORT") {
+            builder = builder.port(port.parse().expect("Invalid SMTP_PORT"));
+        }
+        match (dotenv::var("SMTP_USERNAME"), dotenv::var("SMTP_PASSWORD")) {
+            (Ok(username), Ok(password)) if !username.is_empty() => {
+                builder = builder.credentials(Credentials::new(username, password));
+            }
+            _ => {}
+        }
+
+        Mailer {
+            transport: Some(builder.build()),
+            from,
+            log_bodies: false,
+            base_url,
+        }
+    }
+
+    /// Renders `emails/{template}` with `data` and sends it as a plain text email.
+    pub async fn send<T: Serialize>(
+        &self,
+        hb: &Handlebars<'_>,
+        to: &str,
+        subject: &str,
+        template: &str,
+        data: &T,
+    ) -> Result<()> {
+        let body = hb.render(&format!("emails/{}", template), data)?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse()?)
+            .subject(subject)
+            .body(body)?;
+
+        match &self.transport {
+            Some(transport) => {
+                let transport = transport.clone();
+                web::block(move || transport.send(&message))
+                    .await
+                    .map_err(|e| anyhow!("Failed to send email: {:?}", e))?;
+            }
+            None if self.log_bodies => log::info!(
+                "Email to {}:\n{}",
+                to,
+                String::from_utf8_lossy(&message.formatted())
+            ),
+            None => log::info!("Email to {}: {}", to, subject),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::thread;
+
+    // Accepts every connection, the transport's pool opens an idle one of its own. Returns the
+    // port and the messages it received, recipients are refused with `reject`.
+    fn smtp_sink(reject: bool) -> (u16, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sent, received) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let sent = sent.clone();
+                thread::spawn(move || serve_smtp(stream.unwrap(), reject, sent));
+            }
+        });
+        (port, received)
+    }
+
+    fn serve_smtp(stream: TcpStream, reject: bool, sent: Sender<String>) {
+        let mut writer = stream.try_clone().unwrap();
+        let mut lines = BufReader::new(stream).lines().filter_map(Result::ok);
+        writer.write_all(b"220 localhost ESMTP\r\n").unwrap();
+        while let Some(line) = lines.next() {
+            let command = line.to_ascii_uppercase();
+            let reply: &[u8] = if command.starts_with("RCPT") && reject {
+                b"550 No such user\r\n"
+            } else if command.starts_with("DATA") {
+                writer.write_all(b"354 Go ahead\r\n").unwrap();
+                let data: Vec<String> = lines.by_ref().take_while(|line| line != ".").collect();
+                sent.send(data.join("\n")).unwrap();
+                b"250 Queued\r\n"
+            } else if command.starts_with("QUIT") {
+                b"221 Bye\r\n"
+            } else {
+                b"250 OK\r\n"
+            };
+            writer.write_all(reply).unwrap();
+        }
+    }
+
+    fn mailer(port: u16) -> Mailer {
+        Mailer {
+            transport: Some(
+                SmtpTransport::builder_dangerous("127.0.0.1")
+                    .port(port)
+                    .build(),
+            ),
+            from: "MiniPress <noreply@localhost>".parse().unwrap(),
+            log_bodies: false,
+            base_url: "https://example.com".to_string(),
+        }
+    }
+
+    fn handlebars() -> Handlebars<'static> {
+        let mut hb = Handlebars::new();
+        hb.register_template_file(
+            "emails/password_reset",
+            "resources/templates/emails/password_reset.hbs",
+        )
+        .unwrap();
+        hb.set_strict_mode(true);
+        hb
+    }
+
+    async fn send_reset(mailer: &Mailer) -> Result<()> {
+        let data = json!({
+            "username": "jane",
+            "url": format!("{}/password/reset/abc-123", mailer.base_url),
+        });
+        let hb = handlebars();
+        mailer
+            .send(
+                &hb,
+                "jane@example.com",
+                "Reset your password",
+                "password_reset",
+                &data,
+            )
+            .await
+    }
+
+    #[actix_rt::test]
+    async fn delivers_the_password_reset_email() {
+        let (port, received) = smtp_sink(false);
+        send_reset(&mailer(port)).await.unwrap();
+
+        let message = received.recv().unwrap();
+        assert!(message.contains("From: MiniPress <noreply@localhost>"));
+        assert!(message.contains("To: jane@example.com"));
+        assert!(message.contains("Subject: Reset your password"));
+        assert!(message.contains("Hi jane,"));
+        assert!(message.contains("\nhttps://example.com/password/reset/abc-123\n"));
+    }
+
+    #[actix_rt::test]
+    async fn reports_a_refused_recipient() {
+        let (port, received) = smtp_sink(true);
+        assert!(send_reset(&mailer(port)).await.is_err());
+        assert!(received.try_recv().is_err());
+    }
+}
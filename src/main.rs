@@ -1,8 +1,20 @@
+mod client_ip;
+mod csrf;
 mod database;
 mod handlers;
+mod login;
+mod mailer;
+mod markdown;
 mod middleware;
 pub mod models;
+mod password;
+mod rate_limit;
+mod sanitizer;
+mod session;
+mod setup;
 mod template_helpers;
+mod token;
+mod totp;
 
 #[macro_use]
 extern crate serde_derive;
@@ -10,9 +22,14 @@ extern crate dotenv;
 
 use crate::database::setup_database_pool;
 use crate::handlers::init;
+use crate::mailer::Mailer;
+use crate::rate_limit::RateLimiter;
+use crate::session::SessionCache;
+use crate::setup::SetupToken;
 use actix_files as fs;
 use actix_identity::{CookieIdentityPolicy, IdentityService};
 use actix_session::CookieSession;
+use actix_web::cookie::SameSite;
 use actix_web::middleware::{Compress, Logger};
 use actix_web::{web, App, HttpServer};
 use dotenv::dotenv;
@@ -41,6 +58,22 @@ async fn main() -> std::io::Result<()> {
         Err(e) => eprintln!("Failed to setup logger: {}", e),
     }
 
+    // `minipress create-admin --github-login <name>`
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("create-admin") {
+        let db_pool = setup_database_pool().await;
+        match setup::create_admin(&args[2..], &db_pool).await {
+            Ok(user) => {
+                println!("{} is a super admin now", user.username);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     // load ssl keys
     // to create a self-signed temporary cert for testing:
     // `openssl req -x509 -newkey rsa:4096 -nodes -keyout key.pem -out cert.pem -days 365 -subj '/CN=localhost'`
@@ -69,16 +102,33 @@ async fn main() -> std::io::Result<()> {
         .unwrap();
     handlebars.set_strict_mode(true);
     let handlebars_ref = web::Data::new(handlebars);
+    // shared by all workers
+    let session_cache = web::Data::new(SessionCache::default());
+    let mailer = web::Data::new(Mailer::from_env());
+    let rate_limiter = web::Data::new(RateLimiter::from_env(db_pool.clone()));
+    let setup_token = web::Data::new(SetupToken::open(&db_pool).await);
+    let secret_key = dotenv::var("SECRET_KEY").unwrap();
 
     HttpServer::new(move || {
         App::new()
             // middlewares
+            // inside the error handlers so they render its 429 page
+            .wrap(middleware::RateLimit::new(rate_limiter.clone()))
+            // inside the session, which holds the token, and the error handlers
+            .wrap(middleware::Csrf)
             .wrap(middleware::error_handlers())
             .wrap(Logger::default())
-            .wrap(CookieSession::signed(&[0; 32]).secure(true))
+            // encrypted, it holds the PKCE verifier of pending logins and new TOTP secrets.
+            // Both cookies are left out of posts from other sites, Lax and not Strict because the
+            // OAuth callbacks are navigations from the provider that need them.
+            .wrap(
+                CookieSession::private(secret_key.as_bytes())
+                    .secure(true)
+                    .same_site(SameSite::Lax),
+            )
             .wrap(Compress::default())
             .wrap(IdentityService::new(
-                CookieIdentityPolicy::new(dotenv::var("SECRET_KEY").unwrap().as_ref())
+                CookieIdentityPolicy::new(secret_key.as_bytes())
                     .name("auth")
                     .path("/")
                     .domain(
@@ -87,11 +137,16 @@ async fn main() -> std::io::Result<()> {
                             .as_str(),
                     )
                     .max_age_time(Duration::days(1))
+                    .same_site(SameSite::Lax)
                     .secure(true), // this can only be true if you have https
             ))
             // data
             .data(db_pool.clone())
             .app_data(handlebars_ref.clone())
+            .app_data(session_cache.clone())
+            .app_data(mailer.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(setup_token.clone())
             // services
             .service(
                 fs::Files::new("/static", "static")
@@ -0,0 +1,71 @@
+use crate::models::user::Role;
+use crate::sanitizer;
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+use slug::slugify;
+use std::collections::HashSet;
+
+/// Renders post markdown into html.
+///
+/// Tables, footnotes, strikethrough and task lists are enabled, and every heading gets an `id`
+/// derived from its text so sections can be linked to. The sanitizer prefixes ids, so that's
+/// e.g. `/2020/11/21/my-post#user-content-installation`.
+/// The output is sanitized with the policy of `role`, which should be the role of the author.
+pub fn render(markdown: &str, role: &Role) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+
+    let mut events = Vec::new();
+    let mut used_ids = HashSet::new();
+    // events of the heading currently being parsed, they're buffered until the end of the
+    // heading because the id depends on the whole heading text
+    let mut heading: Option<Vec<Event>> = None;
+    for event in Parser::new_ext(markdown, options) {
+        match event {
+            Event::Start(Tag::Heading(_)) => heading = Some(Vec::new()),
+            Event::End(Tag::Heading(level)) => {
+                let inner = heading.take().unwrap_or_default();
+                let text: String = inner
+                    .iter()
+                    .filter_map(|e| match e {
+                        Event::Text(t) | Event::Code(t) => Some(t.as_ref()),
+                        _ => None,
+                    })
+                    .collect();
+                let id = unique_id(&text, &mut used_ids);
+                events.push(Event::Html(CowStr::from(format!(
+                    "<h{} id=\"{}\">",
+                    level, id
+                ))));
+                events.extend(inner);
+                events.push(Event::Html(CowStr::from(format!("</h{}>\n", level))));
+            }
+            e => match heading.as_mut() {
+                Some(inner) => inner.push(e),
+                None => events.push(e),
+            },
+        }
+    }
+
+    let mut out = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut out, events.into_iter());
+    sanitizer::sanitize(&out, role)
+}
+
+// Slugifies the heading text, appending a counter if the same id was already used in the post.
+fn unique_id(text: &str, used_ids: &mut HashSet<String>) -> String {
+    let mut base = slugify(text);
+    if base.is_empty() {
+        base = "section".to_string();
+    }
+    let mut id = base.clone();
+    let mut n = 1;
+    while used_ids.contains(&id) {
+        id = format!("{}-{}", base, n);
+        n += 1;
+    }
+    used_ids.insert(id.clone());
+    id
+}
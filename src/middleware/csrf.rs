@@ -0,0 +1,128 @@
+use crate::csrf::{CsrfToken, CSRF_FIELD};
+use crate::middleware::render_error;
+use crate::session::bearer_token;
+use actix_http::h1;
+use actix_service::{Service, Transform};
+use actix_session::UserSession;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::BytesMut;
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use url::form_urlencoded;
+
+/// The largest body that is read to find the token, the default limit of `web::Form`.
+const MAX_FORM_SIZE: usize = 16_384;
+
+/// Turns away posts from other sites, see `CsrfToken`. Every request that can change something
+/// needs the token of the session in its form. Requests with an api token and json requests are
+/// left alone, other sites can't make a browser send those.
+pub struct Csrf;
+
+impl<S, B> Transform<S> for Csrf
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CsrfMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CsrfMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+pub struct CsrfMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service for CsrfMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        if !protected(&req) {
+            return Box::pin(self.service.borrow_mut().call(req));
+        }
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let mut body = BytesMut::new();
+            let mut payload = req.take_payload();
+            while let Some(chunk) = payload.next().await {
+                let chunk = chunk?;
+                if body.len() + chunk.len() > MAX_FORM_SIZE {
+                    return Ok(reject(
+                        req,
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        "The form is too large.",
+                    ));
+                }
+                body.extend_from_slice(&chunk);
+            }
+
+            let posted = form_urlencoded::parse(&body)
+                .find(|(name, _)| name == CSRF_FIELD)
+                .map(|(_, value)| value.into_owned());
+            let valid = match posted {
+                Some(token) => CsrfToken::matches(&req.get_session(), &token),
+                None => false,
+            };
+            if !valid {
+                return Ok(reject(
+                    req,
+                    StatusCode::FORBIDDEN,
+                    "This form has expired, please go back, reload the page and try again.",
+                ));
+            }
+
+            // the handler reads the form again
+            let (_, mut payload) = h1::Payload::create(true);
+            payload.unread_data(body.freeze());
+            req.set_payload(payload.into());
+            let response = service.borrow_mut().call(req);
+            response.await
+        })
+    }
+}
+
+// Whether the request needs a token: anything but a read, unless it's authenticated with an api
+// token or sends json, which other sites can't make a browser do without asking first.
+fn protected(req: &ServiceRequest) -> bool {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return false;
+    }
+    if bearer_token(req.headers()).is_some() {
+        return false;
+    }
+    let content_type = req.content_type();
+    content_type != "application/json" && !content_type.ends_with("+json")
+}
+
+fn reject<B>(req: ServiceRequest, status: StatusCode, message: &str) -> ServiceResponse<B> {
+    let (req, _) = req.into_parts();
+    let response = render_error(&req, status, message, "Invalid form", None);
+    ServiceResponse::new(req, response.into_body())
+}
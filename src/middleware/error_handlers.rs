@@ -1,11 +1,11 @@
-use crate::models::user::ToUser;
+use crate::models::Suspension;
+use crate::session::LoggedUser;
 use actix_http::{body::Body, Response};
-use actix_identity::Identity;
 use actix_web::dev::Payload;
 use actix_web::dev::ServiceResponse;
-use actix_web::http::StatusCode;
+use actix_web::http::{header, StatusCode};
 use actix_web::middleware::errhandlers::{ErrorHandlerResponse, ErrorHandlers};
-use actix_web::{web, FromRequest, Result};
+use actix_web::{web, FromRequest, HttpRequest, Result};
 use handlebars::Handlebars;
 use serde_json::json;
 
@@ -13,47 +13,81 @@ use serde_json::json;
 pub fn error_handlers() -> ErrorHandlers<Body> {
     ErrorHandlers::new()
         .handler(StatusCode::NOT_FOUND, not_found)
+        .handler(StatusCode::TOO_MANY_REQUESTS, too_many_requests)
         .handler(StatusCode::INTERNAL_SERVER_ERROR, internal_server_error)
 }
 
 // Error handler for a 500 internal server error.
-fn internal_server_error<B>(res: ServiceResponse<B>) -> Result<ErrorHandlerResponse<B>> {
-    let response = get_error_response(&res, "Internal server error.", "Internal error");
-    Ok(ErrorHandlerResponse::Response(
-        res.into_response(response.into_body()),
+fn internal_server_error<B: 'static>(res: ServiceResponse<B>) -> Result<ErrorHandlerResponse<B>> {
+    Ok(error_page(res, "Internal server error.", "Internal error"))
+}
+
+// Error handler for a 429 too many requests error, see `RateLimit`.
+fn too_many_requests<B: 'static>(res: ServiceResponse<B>) -> Result<ErrorHandlerResponse<B>> {
+    Ok(error_page(
+        res,
+        "Too many attempts, please wait a few minutes before you try again.",
+        "Too many requests",
     ))
 }
 
 // Error handler for a 404 Page not found error.
-fn not_found<B>(res: ServiceResponse<B>) -> Result<ErrorHandlerResponse<B>> {
-    let response = get_error_response(
-        &res,
+fn not_found<B: 'static>(res: ServiceResponse<B>) -> Result<ErrorHandlerResponse<B>> {
+    Ok(error_page(
+        res,
         "Whoopsy daisy! what were you trying to find? It seems that this page does not exists.",
         "Page not found",
-    );
-    Ok(ErrorHandlerResponse::Response(
-        res.into_response(response.into_body()),
     ))
 }
 
+// Replaces the body of the response with the rendered error page. The logged in user is loaded
+// from the database, so this has to be done in a future.
+fn error_page<B: 'static>(
+    res: ServiceResponse<B>,
+    error: &'static str,
+    title: &'static str,
+) -> ErrorHandlerResponse<B> {
+    let user = LoggedUser::from_request(res.request(), &mut Payload::None);
+    ErrorHandlerResponse::Future(Box::pin(async move {
+        let user = user.await.ok();
+        let mut response = get_error_response(&res, error, title, user);
+        // tells clients when to try again after a 429
+        if let Some(retry_after) = res.headers().get(header::RETRY_AFTER) {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.clone());
+        }
+        Ok(res.into_response(response.into_body()))
+    }))
+}
+
 // Generic error handler.
-fn get_error_response<B>(res: &ServiceResponse<B>, error: &str, title: &str) -> Response<Body> {
-    let request = res.request();
+fn get_error_response<B>(
+    res: &ServiceResponse<B>,
+    error: &str,
+    title: &str,
+    user: Option<LoggedUser>,
+) -> Response<Body> {
+    render_error(res.request(), res.status(), error, title, user)
+}
 
+/// Renders the themed error page, for handlers that need to show an error page with a status
+/// that isn't covered by the error handlers, e.g. a failed login.
+pub fn render_error(
+    request: &HttpRequest,
+    status: StatusCode,
+    error: &str,
+    title: &str,
+    user: Option<LoggedUser>,
+) -> Response<Body> {
     // Provide a fallback to a simple plain text response in case an error occurs during the
     // rendering of the error page.
     let fallback = |e: &str| {
-        Response::build(res.status())
+        Response::build(status)
             .content_type("text/plain")
             .body(e.to_string())
     };
 
-    let id = Identity::from_request(request, &mut Payload::None).into_inner();
-    let user = match id {
-        Ok(i) => i.user(),
-        Err(_) => None,
-    };
-
     let hb = request
         .app_data::<web::Data<Handlebars>>()
         .map(|t| t.get_ref());
@@ -62,18 +96,36 @@ fn get_error_response<B>(res: &ServiceResponse<B>, error: &str, title: &str) ->
             let data = json!({
                 "error": error,
                 "title": title,
-                "status_code": res.status().as_str(),
+                "status_code": status.as_str(),
                 "user": user, // user information is needed for the navbar/app_layout
             });
             let body = hb.render("error", &data);
 
             match body {
-                Ok(body) => Response::build(res.status())
-                    .content_type("text/html")
-                    .body(body),
+                Ok(body) => Response::build(status).content_type("text/html").body(body),
                 Err(_) => fallback(error),
             }
         }
         None => fallback(error),
     }
 }
+
+/// Tells a suspended user why they can't sign in, instead of a plain 401 or a login error.
+pub fn render_suspended(request: &HttpRequest, suspension: &Suspension) -> Response<Body> {
+    let status = StatusCode::FORBIDDEN;
+    let message = "This account is suspended.";
+    let hb = request
+        .app_data::<web::Data<Handlebars>>()
+        .map(|t| t.get_ref());
+    let data = json!({
+        "user": null,
+        "until": suspension.until.map(|u| u.format("%B %-d, %Y %H:%M UTC")),
+        "reason": suspension.reason,
+    });
+    match hb.map(|hb| hb.render("suspended", &data)) {
+        Some(Ok(body)) => Response::build(status).content_type("text/html").body(body),
+        _ => Response::build(status)
+            .content_type("text/plain")
+            .body(message),
+    }
+}
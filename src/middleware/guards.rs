@@ -0,0 +1,156 @@
+use crate::models::user::Role;
+use crate::session::LoggedUser;
+use actix_web::dev::Payload;
+use actix_web::{Error, FromRequest, HttpRequest, HttpResponse};
+use futures::future::LocalBoxFuture;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+/// Marker types for `RequireRole`, one per role that can be required.
+// not every role is required by a route yet
+#[allow(dead_code)]
+pub mod roles {
+    use super::MinimumRole;
+    use crate::models::user::Role;
+
+    pub struct SuperAdmin;
+    pub struct Admin;
+    pub struct Editor;
+    pub struct Author;
+    pub struct Contributor;
+    pub struct Subscriber;
+
+    impl MinimumRole for SuperAdmin {
+        const ROLE: Role = Role::SuperAdmin;
+    }
+    impl MinimumRole for Admin {
+        const ROLE: Role = Role::Admin;
+    }
+    impl MinimumRole for Editor {
+        const ROLE: Role = Role::Editor;
+    }
+    impl MinimumRole for Author {
+        const ROLE: Role = Role::Author;
+    }
+    impl MinimumRole for Contributor {
+        const ROLE: Role = Role::Contributor;
+    }
+    impl MinimumRole for Subscriber {
+        const ROLE: Role = Role::Subscriber;
+    }
+}
+
+pub trait MinimumRole {
+    const ROLE: Role;
+}
+
+/// Extractor for a logged in user that has at least the role `R`, e.g.
+/// `user: RequireRole<roles::Editor>` lets editors, admins and super admins through.
+/// Guests get a 401 and users with a lesser role a 403.
+pub struct RequireRole<R: MinimumRole> {
+    user: LoggedUser,
+    role: PhantomData<R>,
+}
+
+impl<R: MinimumRole> RequireRole<R> {
+    pub fn into_inner(self) -> LoggedUser {
+        self.user
+    }
+
+    // Lets the user through if they have at least the role `R`.
+    fn check(user: LoggedUser) -> Result<RequireRole<R>, Error> {
+        if user.role >= R::ROLE {
+            Ok(RequireRole {
+                user,
+                role: PhantomData,
+            })
+        } else {
+            Err(HttpResponse::Forbidden().body("Forbidden").into())
+        }
+    }
+}
+
+impl<R: MinimumRole> Deref for RequireRole<R> {
+    type Target = LoggedUser;
+
+    fn deref(&self) -> &LoggedUser {
+        &self.user
+    }
+}
+
+impl<R: MinimumRole + 'static> FromRequest for RequireRole<R> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<RequireRole<R>, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, pl: &mut Payload) -> Self::Future {
+        let user = LoggedUser::from_request(req, pl);
+        Box::pin(async move { RequireRole::check(user.await?) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::User;
+    use actix_web::http::StatusCode;
+    use sqlx::types::Uuid;
+    use std::time::SystemTime;
+    use time::PrimitiveDateTime;
+
+    fn user(role: Role) -> LoggedUser {
+        let now = PrimitiveDateTime::from(SystemTime::now());
+        User {
+            id: Uuid::nil(),
+            username: "jane".to_string(),
+            email: None,
+            password: None,
+            name: None,
+            avatar_url: None,
+            gravatar_id: None,
+            github_id: None,
+            github_token: None,
+            role,
+            session_version: 0,
+            email_verified: false,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    // Whether `RequireRole<R>` lets a user with `role` through, the others get a 403.
+    fn allows<R: MinimumRole>(role: Role) -> bool {
+        match RequireRole::<R>::check(user(role)) {
+            Ok(user) => {
+                assert_eq!(user.role, role);
+                true
+            }
+            Err(e) => {
+                assert_eq!(e.as_response_error().status_code(), StatusCode::FORBIDDEN);
+                false
+            }
+        }
+    }
+
+    #[test]
+    fn editor_routes_let_editors_and_above_through() {
+        assert!(allows::<roles::Editor>(Role::SuperAdmin));
+        assert!(allows::<roles::Editor>(Role::Admin));
+        assert!(allows::<roles::Editor>(Role::Editor));
+        assert!(!allows::<roles::Editor>(Role::Author));
+        assert!(!allows::<roles::Editor>(Role::Contributor));
+        assert!(!allows::<roles::Editor>(Role::Subscriber));
+        assert!(!allows::<roles::Editor>(Role::Guest));
+    }
+
+    #[test]
+    fn admin_routes_let_admins_and_super_admins_through() {
+        assert!(allows::<roles::Admin>(Role::SuperAdmin));
+        assert!(allows::<roles::Admin>(Role::Admin));
+        assert!(!allows::<roles::Admin>(Role::Editor));
+        assert!(!allows::<roles::Admin>(Role::Author));
+        assert!(!allows::<roles::Admin>(Role::Contributor));
+        assert!(!allows::<roles::Admin>(Role::Subscriber));
+        assert!(!allows::<roles::Admin>(Role::Guest));
+    }
+}
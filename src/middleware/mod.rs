@@ -1,3 +1,9 @@
+mod csrf;
 mod error_handlers;
+mod guards;
+mod rate_limit;
 
-pub use error_handlers::error_handlers;
+pub use csrf::Csrf;
+pub use error_handlers::{error_handlers, render_error, render_suspended};
+pub use guards::{roles, RequireRole};
+pub use rate_limit::RateLimit;
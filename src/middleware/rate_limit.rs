@@ -0,0 +1,164 @@
+use crate::client_ip::client_ip;
+use crate::login::two_factor::PendingTwoFactor;
+use crate::login::AuthProviders;
+use crate::rate_limit::{too_many_requests, Key, RateLimiter};
+use crate::session::SessionIdentity;
+use actix_identity::RequestIdentity;
+use actix_service::{Service, Transform};
+use actix_session::UserSession;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{web, Error};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+/// What a request to a limited endpoint counts as.
+#[derive(Clone, Copy, PartialEq)]
+enum Counts {
+    /// Only requests the handler answered with a client error, e.g. a wrong password.
+    Failures,
+    /// Every request, for endpoints that send emails.
+    Every,
+}
+
+/// Limits the login, two-factor, OAuth callback and password reset endpoints per ip address,
+/// and per account when it's known without reading the body, see `RateLimiter`. Handlers that
+/// get the account from the form, like the password login, check it themselves.
+pub struct RateLimit {
+    limiter: web::Data<RateLimiter>,
+    /// The providers are only registered for the handlers' scope, which isn't entered yet when
+    /// this runs, so the paths are read from the config again.
+    callback_paths: Rc<Vec<String>>,
+}
+
+impl RateLimit {
+    pub fn new(limiter: web::Data<RateLimiter>) -> RateLimit {
+        let callback_paths = AuthProviders::from_env()
+            .iter()
+            .map(|provider| provider.callback_path().to_string())
+            .collect();
+        RateLimit {
+            limiter,
+            callback_paths: Rc::new(callback_paths),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for RateLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            limiter: self.limiter.clone(),
+            callback_paths: self.callback_paths.clone(),
+        })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    limiter: web::Data<RateLimiter>,
+    callback_paths: Rc<Vec<String>>,
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let counts = match limited(&req, &self.callback_paths) {
+            Some(c) => c,
+            None => return Box::pin(self.service.borrow_mut().call(req)),
+        };
+        let keys = keys(&req);
+        let service = self.service.clone();
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            if let Some(wait) = limiter.check_all(&keys).await {
+                return Ok(req.into_response(too_many_requests(wait).into_body()));
+            }
+
+            // not borrowed across the await, other requests on this worker need the service too
+            let response = service.borrow_mut().call(req);
+            let response = response.await?;
+
+            let status = response.status();
+            let failed = status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS;
+            for key in &keys {
+                if failed || counts == Counts::Every {
+                    limiter.fail(key).await;
+                } else if let Key::Account(_) = key {
+                    limiter.clear(key).await;
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+// Which requests are limited, and what they count as.
+fn limited(req: &ServiceRequest, callback_paths: &[String]) -> Option<Counts> {
+    let path = req.path();
+    match *req.method() {
+        Method::POST
+            if path == "/login"
+                || path.starts_with("/login/2fa")
+                || path.starts_with("/account/2fa/")
+                || path == "/account/password"
+                || path.starts_with("/password/reset/") =>
+        {
+            Some(Counts::Failures)
+        }
+        Method::POST if path == "/password/forgot" => Some(Counts::Every),
+        Method::GET if callback_paths.iter().any(|p| p == path) => Some(Counts::Failures),
+        _ => None,
+    }
+}
+
+// The ip address and, for the second factor and account settings, the user of the request.
+fn keys(req: &ServiceRequest) -> Vec<Key> {
+    let mut keys = Vec::new();
+    if let Some(ip) = client_ip(&req.connection_info(), req.peer_addr()) {
+        keys.push(Key::Ip(ip));
+    }
+
+    let path = req.path();
+    let user_id = if path.starts_with("/login/2fa") {
+        PendingTwoFactor::get(&req.get_session()).map(|pending| pending.user_id)
+    } else if path.starts_with("/account/") {
+        req.get_identity()
+            .and_then(|identity| SessionIdentity::parse(&identity))
+            .map(|session| session.user_id)
+    } else {
+        None
+    };
+    if let Some(user_id) = user_id {
+        keys.push(Key::Account(format!("user:{}", user_id)));
+    }
+    keys
+}
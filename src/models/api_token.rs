@@ -0,0 +1,151 @@
+use crate::database::DbPool;
+use crate::models::uuid_serializer;
+use crate::token;
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::types::Uuid;
+use sqlx::{Done, FromRow};
+use time::{Duration, PrimitiveDateTime};
+
+/// What an api token can be used for, stored as e.g. `posts:write`. A token never gets more
+/// than the role of its user allows.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Scope {
+    PostsRead,
+    PostsWrite,
+    PostsReview,
+    UsersRead,
+    UsersWrite,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 5] = [
+        Scope::PostsRead,
+        Scope::PostsWrite,
+        Scope::PostsReview,
+        Scope::UsersRead,
+        Scope::UsersWrite,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::PostsRead => "posts:read",
+            Scope::PostsWrite => "posts:write",
+            Scope::PostsReview => "posts:review",
+            Scope::UsersRead => "users:read",
+            Scope::UsersWrite => "users:write",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Scope> {
+        Scope::ALL.iter().copied().find(|s| s.as_str() == scope)
+    }
+
+    /// Shown next to the checkbox when creating a token.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Scope::PostsRead => "Read drafts, the review queue and review history",
+            Scope::PostsWrite => "Create, edit, delete and submit posts",
+            Scope::PostsReview => "Approve, reject and request changes to posts",
+            Scope::UsersRead => "Read users",
+            Scope::UsersWrite => "Create, edit and delete users",
+        }
+    }
+}
+
+// this struct will be used to represent database record
+#[derive(Serialize, FromRow)]
+pub struct ApiToken {
+    #[serde(with = "uuid_serializer")]
+    pub id: Uuid,
+    #[serde(with = "uuid_serializer")]
+    pub user_id: Uuid,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    /// None for tokens that don't expire.
+    pub expires_at: Option<PrimitiveDateTime>,
+    pub last_used_at: Option<PrimitiveDateTime>,
+    pub created_at: PrimitiveDateTime,
+}
+
+// Implementation for ApiToken struct, the tokens themselves are never stored
+impl ApiToken {
+    /// The tokens of a user, expired ones included so the user sees why a script stopped
+    /// working. The newest first.
+    pub async fn find_by_user_id(user_id: Uuid, pool: &DbPool) -> Result<Vec<ApiToken>> {
+        let tokens = sqlx::query_as!(
+            ApiToken,
+            "
+                SELECT * FROM api_tokens WHERE user_id = $1
+                ORDER BY created_at DESC
+            ",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    /// Creates a token and returns it, it's only shown to the user this once.
+    pub async fn create(
+        user_id: Uuid,
+        name: &str,
+        scopes: &[Scope],
+        lifetime: Option<Duration>,
+        pool: &DbPool,
+    ) -> Result<String> {
+        // the prefix makes leaked tokens easy to recognize, e.g. for secret scanners
+        let token = format!("mp_{}", token::generate());
+        let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_string()).collect();
+        sqlx::query(
+            "
+                INSERT INTO api_tokens (user_id, name, token_hash, scopes, expires_at)
+                VALUES ($1, $2, $3, $4, now() + $5 * interval '1 second')
+            ",
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(token::hash(&token))
+        .bind(scopes)
+        .bind(lifetime.map(|l| l.as_seconds_f64()))
+        .execute(pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// Looks up a token that hasn't expired and records that it was used.
+    pub async fn authenticate(token: &str, pool: &DbPool) -> Result<Option<ApiToken>> {
+        let token = sqlx::query_as!(
+            ApiToken,
+            "
+                UPDATE api_tokens SET last_used_at = now()
+                WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > now())
+                RETURNING *
+            ",
+            token::hash(token),
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|s| s == scope.as_str())
+    }
+
+    /// Revokes a token of a user, the user id is checked so users can only revoke their own.
+    pub async fn delete(id: Uuid, user_id: Uuid, pool: &DbPool) -> Result<u64> {
+        let deleted = sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(deleted.rows_affected())
+    }
+}
@@ -1,10 +1,41 @@
+mod api_token;
 mod post;
+mod post_review;
+mod rate_limit;
+mod session;
+mod setup;
+pub mod suspension;
+mod two_factor;
 pub mod user;
+mod user_deletion;
+mod user_identity;
+mod user_overview;
+pub mod user_profile;
+mod user_token;
 
+pub use api_token::ApiToken;
+pub use api_token::Scope;
 pub use post::Post;
 pub use post::PostRequest;
+pub use post::PostStatus;
+pub use post_review::PostReview;
+pub use post_review::ReviewAction;
+pub use post_review::ReviewRequest;
+pub use rate_limit::RateLimit;
+pub use session::Session;
+pub use setup::Setup;
+pub use suspension::Suspension;
+pub use two_factor::TwoFactor;
 pub use user::User;
 pub use user::UserRequest;
+pub use user_deletion::DeletionOutcome;
+pub use user_deletion::UserDeletion;
+pub use user_identity::UserIdentity;
+pub use user_overview::UserOverview;
+pub use user_profile::ProfileRequest;
+pub use user_profile::UserProfile;
+pub use user_token::TokenPurpose;
+pub use user_token::UserToken;
 pub use uuid as uuid_serializer;
 
 pub mod uuid {
@@ -1,20 +1,120 @@
 use crate::database::DbPool;
-use crate::models::user::User;
+use crate::markdown;
+use crate::models::user::{Role, User};
 use crate::models::uuid_serializer;
 use actix_web::{Error, HttpRequest, HttpResponse, Responder};
 use anyhow::Result;
 use futures::future::{ready, Ready};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use slug::slugify;
 use sqlx::types::Uuid;
 use sqlx::{Done, FromRow};
-use time::PrimitiveDateTime;
+use std::time::SystemTime;
+use time::{Date, PrimitiveDateTime};
 
 // this struct will use to receive user input
 #[derive(Serialize, Deserialize)]
 pub struct PostRequest {
     pub title: String,
     pub content: String,
+    /// New posts are drafts and updated ones keep their status when it's left out.
+    #[serde(default)]
+    pub status: Option<PostStatus>,
+    /// When the post goes live. Required for scheduled posts, defaults to now for published ones.
+    #[serde(default)]
+    pub published_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(sqlx::Type, PartialEq, Clone, Copy, Debug)]
+#[repr(i16)]
+pub enum PostStatus {
+    /// Only visible to the author and editors.
+    Draft = 1,
+    /// Written by a contributor and waiting for an editor to publish it.
+    PendingReview = 2,
+    /// Visible to everyone from `published_at` on.
+    Published = 3,
+    /// Same as published, but `published_at` was set to a date in the future.
+    Scheduled = 4,
+    /// Published, but only visible to the author and editors.
+    Private = 5,
+}
+
+impl Default for PostStatus {
+    fn default() -> Self {
+        PostStatus::Draft
+    }
+}
+
+impl PostStatus {
+    /// Whether setting this status makes the post publicly visible (now or in the future).
+    /// Only authors and above are allowed to do that.
+    pub fn is_publishing(self) -> bool {
+        self == PostStatus::Published || self == PostStatus::Scheduled
+    }
+
+    /// Contributors can only save drafts, they submit them with `/post/{uuid}/submit`.
+    pub fn can_be_set_by(self, role: Role) -> bool {
+        self == PostStatus::Draft || role.can_publish()
+    }
+}
+
+impl Serialize for PostStatus {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let slug = match self {
+            PostStatus::Draft => "draft",
+            PostStatus::PendingReview => "pending-review",
+            PostStatus::Published => "published",
+            PostStatus::Scheduled => "scheduled",
+            PostStatus::Private => "private",
+        };
+        serializer.serialize_str(slug)
+    }
+}
+
+impl<'de> Deserialize<'de> for PostStatus {
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let slug = String::deserialize(deserializer)?;
+        let status = match slug.as_str() {
+            "draft" => PostStatus::Draft,
+            "pending-review" => PostStatus::PendingReview,
+            "published" => PostStatus::Published,
+            "scheduled" => PostStatus::Scheduled,
+            "private" => PostStatus::Private,
+            _ => {
+                return Err(serde::de::Error::unknown_variant(
+                    &slug,
+                    &[
+                        "draft",
+                        "pending-review",
+                        "published",
+                        "scheduled",
+                        "private",
+                    ],
+                ))
+            }
+        };
+        Ok(status)
+    }
+}
+
+impl PostRequest {
+    /// Checks that the requested status is consistent with `published_at`.
+    pub fn validate(&self) -> std::result::Result<(), &'static str> {
+        if self.status == Some(PostStatus::Scheduled) {
+            match self.published_at {
+                Some(at) if at > PrimitiveDateTime::from(SystemTime::now()) => {}
+                _ => return Err("Scheduled posts need a published_at date in the future"),
+            }
+        }
+        Ok(())
+    }
 }
 
 // this struct will be used to represent database record
@@ -28,6 +128,10 @@ pub struct Post {
     pub slug: String,
     pub excerpt: String,
     pub content: String,
+    /// `content` rendered into html, refreshed whenever the content changes.
+    pub content_html: String,
+    pub status: PostStatus,
+    pub published_at: Option<PrimitiveDateTime>,
     pub created_at: PrimitiveDateTime,
     pub updated_at: PrimitiveDateTime,
 }
@@ -46,16 +150,138 @@ impl Responder for Post {
     }
 }
 
+// Capability checks, shared by the JSON api and the html pages so they can't drift apart.
+impl Post {
+    /// Drafts, private and scheduled posts can only be read by the users who can edit them.
+    pub fn can_be_read_by(&self, user: Option<&User>) -> bool {
+        self.is_public() || user.map_or(false, |u| self.can_be_edited_by(u))
+    }
+
+    /// Contributors and authors can only edit their own posts, editors and above anyone's.
+    pub fn can_be_edited_by(&self, user: &User) -> bool {
+        user.role.can_review() || (self.user_id == user.id && user.role >= Role::Contributor)
+    }
+
+    /// Only the owner of the post, admins and super admins can delete it.
+    pub fn can_be_deleted_by(&self, user: &User) -> bool {
+        user.role >= Role::Admin || (self.user_id == user.id && user.role >= Role::Contributor)
+    }
+}
+
+impl Post {
+    /// Whether anyone, including guests, can read the post.
+    pub fn is_public(&self) -> bool {
+        self.status.is_publishing()
+            && match self.published_at {
+                Some(at) => at <= PrimitiveDateTime::from(SystemTime::now()),
+                None => false,
+            }
+    }
+
+    /// The public url of the post in the `/YYYY/MM/DD/slug` format.
+    pub fn permalink(&self) -> String {
+        format!("/{}/{}", self.created_at.format("%Y/%m/%d"), self.slug)
+    }
+
+    /// The rendered html of the post. Posts written before the html was cached in the database
+    /// have an empty `content_html` and are rendered on the fly with the strictest sanitizer
+    /// policy, since the author's role isn't known here.
+    pub fn html(&self) -> String {
+        if self.content_html.is_empty() && !self.content.is_empty() {
+            markdown::render(&self.content, &Role::Guest)
+        } else {
+            self.content_html.clone()
+        }
+    }
+}
+
 // Implementation for Post struct, functions for read/write/update and delete post from database
 impl Post {
     pub async fn find_all(pool: &DbPool) -> Result<Vec<Post>> {
         let posts = sqlx::query_as!(
             Post,
-            "
-                SELECT id, user_id, title, slug, excerpt, content, created_at, updated_at
+            r#"
+                SELECT id, user_id, title, slug, excerpt, content, content_html,
+                status as "status: PostStatus", published_at, created_at, updated_at
                     FROM posts
                 ORDER BY created_at
+            "#
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(posts)
+    }
+
+    /// Posts that are publicly visible, newest first. A `limit` of `None` returns all of them.
+    pub async fn find_published(limit: Option<i64>, pool: &DbPool) -> Result<Vec<Post>> {
+        let posts = sqlx::query_as!(
+            Post,
+            r#"
+                SELECT id, user_id, title, slug, excerpt, content, content_html,
+                status as "status: PostStatus", published_at, created_at, updated_at
+                    FROM posts
+                WHERE status IN (3, 4) AND published_at <= now()
+                ORDER BY published_at DESC
+                LIMIT $1
+            "#,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(posts)
+    }
+
+    /// A page of the publicly visible posts of a user, newest first, and the number of them.
+    pub async fn find_published_by_user_id(
+        user_id: Uuid,
+        page: i64,
+        per_page: i64,
+        pool: &DbPool,
+    ) -> Result<(Vec<Post>, i64)> {
+        let posts = sqlx::query_as!(
+            Post,
+            r#"
+                SELECT id, user_id, title, slug, excerpt, content, content_html,
+                status as "status: PostStatus", published_at, created_at, updated_at
+                    FROM posts
+                WHERE user_id = $1 AND status IN (3, 4) AND published_at <= now()
+                ORDER BY published_at DESC
+                LIMIT $2 OFFSET $3
+            "#,
+            user_id,
+            per_page,
+            (page - 1).max(0) * per_page
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let (total,): (i64,) = sqlx::query_as(
             "
+                SELECT count(*) FROM posts
+                WHERE user_id = $1 AND status IN (3, 4) AND published_at <= now()
+            ",
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok((posts, total))
+    }
+
+    /// Posts with the given status, the ones that haven't been touched for the longest first.
+    pub async fn find_by_status(status: PostStatus, pool: &DbPool) -> Result<Vec<Post>> {
+        let posts = sqlx::query_as!(
+            Post,
+            r#"
+                SELECT id, user_id, title, slug, excerpt, content, content_html,
+                status as "status: PostStatus", published_at, created_at, updated_at
+                    FROM posts
+                WHERE status = $1
+                ORDER BY updated_at
+            "#,
+            status as i16
         )
         .fetch_all(pool)
         .await?;
@@ -66,9 +292,12 @@ impl Post {
     pub async fn find_by_id(id: Uuid, pool: &DbPool) -> Result<Post> {
         let post = sqlx::query_as!(
             Post,
-            "
-                SELECT * FROM posts WHERE id = $1
-            ",
+            r#"
+                SELECT id, user_id, title, slug, excerpt, content, content_html,
+                status as "status: PostStatus", published_at, created_at, updated_at
+                    FROM posts
+                WHERE id = $1
+            "#,
             id
         )
         .fetch_one(pool)
@@ -77,6 +306,29 @@ impl Post {
         Ok(post)
     }
 
+    /// Finds a publicly visible post by the date it was created on and its slug. Slugs are only
+    /// unique per day, so if more than one post matches the oldest one wins.
+    pub async fn find_by_permalink(date: Date, slug: &str, pool: &DbPool) -> Result<Post> {
+        let post = sqlx::query_as!(
+            Post,
+            r#"
+                SELECT id, user_id, title, slug, excerpt, content, content_html,
+                status as "status: PostStatus", published_at, created_at, updated_at
+                    FROM posts
+                WHERE created_at::date = $1 AND slug = $2
+                    AND status IN (3, 4) AND published_at <= now()
+                ORDER BY created_at
+                LIMIT 1
+            "#,
+            date,
+            slug
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(post)
+    }
+
     pub async fn create(post: PostRequest, pool: &DbPool, logged_user: User) -> Result<Post> {
         let slug = slugify(post.title.clone());
         // take the first 55 words as the excerpt.
@@ -86,14 +338,31 @@ impl Post {
             .take(55)
             .collect::<Vec<&str>>()
             .join(" ");
+        let content_html = markdown::render(&post.content, &logged_user.role);
+        let status = post.status.unwrap_or_default();
+        let published_at = match status {
+            PostStatus::Published => Some(
+                post.published_at
+                    .unwrap_or_else(|| PrimitiveDateTime::from(SystemTime::now())),
+            ),
+            _ => post.published_at,
+        };
         let post = sqlx::query_as!(
             Post,
-            "INSERT INTO posts (user_id, title, slug, excerpt, content) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+            r#"
+                INSERT INTO posts (user_id, title, slug, excerpt, content, content_html, status, published_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                RETURNING id, user_id, title, slug, excerpt, content, content_html,
+                status as "status: PostStatus", published_at, created_at, updated_at
+            "#,
             logged_user.id,
             post.title,
             slug,
             excerpt,
             post.content,
+            content_html,
+            status as i16,
+            published_at,
         )
         .fetch_one(pool)
         .await?;
@@ -111,14 +380,45 @@ impl Post {
             .take(55)
             .collect::<Vec<&str>>()
             .join(" ");
+        // the html is sanitized with the policy of the author, not the user doing the update
+        let author = sqlx::query!(
+            r#"
+                SELECT users.role as "role: Role" FROM posts
+                    JOIN users ON users.id = posts.user_id
+                WHERE posts.id = $1
+            "#,
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+        let content_html = markdown::render(&post.content, &author.role);
+        // a post that is already published keeps its original publication date unless a new
+        // one is given, and so does one whose status is left as it is. A scheduled post that is
+        // published before its date is published now, `status` is still the previous one here
         let post = sqlx::query_as!(
             Post,
-            "
-                UPDATE posts SET title = $1, content = $2, excerpt = $3 WHERE id = $4 RETURNING *
-            ",
+            r#"
+                UPDATE posts SET title = $1, content = $2, content_html = $3, excerpt = $4,
+                status = COALESCE($5, status),
+                published_at = CASE
+                    WHEN COALESCE($5, status) = 3 THEN COALESCE(
+                        $6,
+                        CASE WHEN status = 4 THEN LEAST(published_at, now()) ELSE published_at END,
+                        now()
+                    )
+                    WHEN $5 IS NULL THEN COALESCE($6, published_at)
+                    ELSE $6
+                END
+                WHERE id = $7
+                RETURNING id, user_id, title, slug, excerpt, content, content_html,
+                status as "status: PostStatus", published_at, created_at, updated_at
+            "#,
             post.title,
             post.content,
+            content_html,
             excerpt,
+            post.status.map(|s| s as i16),
+            post.published_at,
             id,
         )
         .fetch_one(pool)
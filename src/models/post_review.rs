@@ -0,0 +1,144 @@
+use crate::database::DbPool;
+use crate::models::uuid_serializer;
+use crate::models::PostStatus;
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::types::Uuid;
+use sqlx::{Done, FromRow};
+use time::PrimitiveDateTime;
+
+// this struct will use to receive user input
+#[derive(Serialize, Deserialize, Default)]
+pub struct ReviewRequest {
+    pub note: Option<String>,
+}
+
+#[derive(sqlx::Type, PartialEq, Clone, Copy, Debug)]
+#[repr(i16)]
+pub enum ReviewAction {
+    /// A contributor submitted their draft for review.
+    Submitted = 1,
+    /// An editor published the post.
+    Approved = 2,
+    /// An editor sent the post back to the author with a note on what to change.
+    ChangesRequested = 3,
+    /// An editor decided the post won't be published, it goes back to being a draft.
+    Rejected = 4,
+}
+
+impl ReviewAction {
+    /// The status a post needs to be in for this action to be taken.
+    pub fn required_status(self) -> PostStatus {
+        match self {
+            ReviewAction::Submitted => PostStatus::Draft,
+            _ => PostStatus::PendingReview,
+        }
+    }
+
+    /// The status of the post after this action.
+    pub fn resulting_status(self) -> PostStatus {
+        match self {
+            ReviewAction::Submitted => PostStatus::PendingReview,
+            ReviewAction::Approved => PostStatus::Published,
+            ReviewAction::ChangesRequested | ReviewAction::Rejected => PostStatus::Draft,
+        }
+    }
+}
+
+impl Serialize for ReviewAction {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let slug = match self {
+            ReviewAction::Submitted => "submitted",
+            ReviewAction::Approved => "approved",
+            ReviewAction::ChangesRequested => "changes-requested",
+            ReviewAction::Rejected => "rejected",
+        };
+        serializer.serialize_str(slug)
+    }
+}
+
+// this struct will be used to represent database record
+#[derive(Serialize, FromRow)]
+pub struct PostReview {
+    #[serde(with = "uuid_serializer")]
+    pub id: Uuid,
+    #[serde(with = "uuid_serializer")]
+    pub post_id: Uuid,
+    /// The user who took the action, the author for submissions and the editor otherwise.
+    #[serde(with = "uuid_serializer")]
+    pub user_id: Uuid,
+    pub action: ReviewAction,
+    pub note: Option<String>,
+    pub created_at: PrimitiveDateTime,
+}
+
+// Implementation for PostReview struct, functions for reading and recording review transitions
+impl PostReview {
+    /// The review history of a post, oldest first.
+    pub async fn find_by_post_id(post_id: Uuid, pool: &DbPool) -> Result<Vec<PostReview>> {
+        let reviews = sqlx::query_as!(
+            PostReview,
+            r#"
+                SELECT id, post_id, user_id, action as "action: ReviewAction", note, created_at
+                    FROM post_reviews
+                WHERE post_id = $1
+                ORDER BY created_at
+            "#,
+            post_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(reviews)
+    }
+
+    /// Moves the post to the status the action leads to and records who did it and when.
+    /// Returns `None` if the post doesn't exist or isn't in the status the action applies to.
+    pub async fn create(
+        post_id: Uuid,
+        user_id: Uuid,
+        action: ReviewAction,
+        note: Option<String>,
+        pool: &DbPool,
+    ) -> Result<Option<PostReview>> {
+        let mut tx = pool.begin().await?;
+
+        // approved posts keep a publication date the author may have picked
+        let updated = sqlx::query(
+            "
+                UPDATE posts SET status = $1,
+                published_at = CASE WHEN $1 = 3 THEN COALESCE(published_at, now()) ELSE published_at END
+                WHERE id = $2 AND status = $3
+            ",
+        )
+        .bind(action.resulting_status() as i16)
+        .bind(post_id)
+        .bind(action.required_status() as i16)
+        .execute(&mut tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            tx.rollback().await?;
+            return Ok(None);
+        }
+
+        let review = sqlx::query_as!(
+            PostReview,
+            r#"
+                INSERT INTO post_reviews (post_id, user_id, action, note) VALUES ($1, $2, $3, $4)
+                RETURNING id, post_id, user_id, action as "action: ReviewAction", note, created_at
+            "#,
+            post_id,
+            user_id,
+            action as i16,
+            note,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(review))
+    }
+}
@@ -0,0 +1,76 @@
+use crate::database::DbPool;
+use anyhow::Result;
+use sqlx::Done;
+use std::time::Duration;
+
+/// The failures counted for a key of the rate limiter, see `RateLimiter`.
+pub struct RateLimit {
+    pub failures: u32,
+    /// Since the last failure, measured by the database so instances with a different clock
+    /// agree.
+    pub elapsed: Duration,
+}
+
+// Implementation for RateLimit struct, used by the postgres store of the rate limiter
+impl RateLimit {
+    pub async fn find(key: &str, pool: &DbPool) -> Result<Option<RateLimit>> {
+        let row: Option<(i32, f64)> = sqlx::query_as(
+            "
+                SELECT failures, extract(epoch from now() - last_failure_at)::float8
+                FROM rate_limits WHERE key = $1
+            ",
+        )
+        .bind(key)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(row.map(|(failures, elapsed)| RateLimit {
+            failures: failures.max(0) as u32,
+            elapsed: Duration::from_secs_f64(elapsed.max(0.0)),
+        }))
+    }
+
+    /// Counts a failure and returns how many there are now. Failures older than `forget_after`
+    /// are forgotten and the count starts over.
+    pub async fn record(key: &str, forget_after: Duration, pool: &DbPool) -> Result<u32> {
+        let (failures,): (i32,) = sqlx::query_as(
+            "
+                INSERT INTO rate_limits (key, failures, last_failure_at) VALUES ($1, 1, now())
+                ON CONFLICT (key) DO UPDATE SET
+                    failures = CASE
+                        WHEN rate_limits.last_failure_at < now() - $2 * interval '1 second' THEN 1
+                        ELSE rate_limits.failures + 1
+                    END,
+                    last_failure_at = now()
+                RETURNING failures
+            ",
+        )
+        .bind(key)
+        .bind(forget_after.as_secs_f64())
+        .fetch_one(pool)
+        .await?;
+
+        Ok(failures.max(0) as u32)
+    }
+
+    pub async fn delete(key: &str, pool: &DbPool) -> Result<u64> {
+        let deleted = sqlx::query("DELETE FROM rate_limits WHERE key = $1")
+            .bind(key)
+            .execute(pool)
+            .await?;
+
+        Ok(deleted.rows_affected())
+    }
+
+    /// Deletes the failures that are forgotten already, so the table doesn't keep growing.
+    pub async fn delete_expired(forget_after: Duration, pool: &DbPool) -> Result<u64> {
+        let deleted = sqlx::query(
+            "DELETE FROM rate_limits WHERE last_failure_at < now() - $1 * interval '1 second'",
+        )
+        .bind(forget_after.as_secs_f64())
+        .execute(pool)
+        .await?;
+
+        Ok(deleted.rows_affected())
+    }
+}
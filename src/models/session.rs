@@ -0,0 +1,90 @@
+use crate::database::DbPool;
+use crate::models::uuid_serializer;
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::types::Uuid;
+use sqlx::{Done, FromRow};
+use time::PrimitiveDateTime;
+
+// this struct will be used to represent database record
+#[derive(Serialize, FromRow)]
+pub struct Session {
+    #[serde(with = "uuid_serializer")]
+    pub id: Uuid,
+    #[serde(with = "uuid_serializer")]
+    pub user_id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: PrimitiveDateTime,
+    pub last_seen_at: PrimitiveDateTime,
+}
+
+// Implementation for Session struct, functions for read/write and delete sessions from database
+impl Session {
+    /// The sessions of a user, the most recently used first.
+    pub async fn find_by_user_id(user_id: Uuid, pool: &DbPool) -> Result<Vec<Session>> {
+        let sessions = sqlx::query_as!(
+            Session,
+            "
+                SELECT * FROM sessions WHERE user_id = $1
+                ORDER BY last_seen_at DESC
+            ",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    pub async fn create(
+        user_id: Uuid,
+        user_agent: Option<String>,
+        ip: Option<String>,
+        pool: &DbPool,
+    ) -> Result<Session> {
+        let session = sqlx::query_as!(
+            Session,
+            "INSERT INTO sessions (user_id, user_agent, ip) VALUES ($1, $2, $3) RETURNING *",
+            user_id,
+            user_agent,
+            ip,
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(session)
+    }
+
+    /// Updates the last seen time of a session. Returns 0 if the session was revoked.
+    pub async fn touch(id: Uuid, user_id: Uuid, pool: &DbPool) -> Result<u64> {
+        let updated =
+            sqlx::query("UPDATE sessions SET last_seen_at = now() WHERE id = $1 AND user_id = $2")
+                .bind(id)
+                .bind(user_id)
+                .execute(pool)
+                .await?;
+
+        Ok(updated.rows_affected())
+    }
+
+    /// Deletes a session of a user, the user id is checked so users can only revoke their own.
+    pub async fn delete(id: Uuid, user_id: Uuid, pool: &DbPool) -> Result<u64> {
+        let deleted = sqlx::query("DELETE FROM sessions WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(deleted.rows_affected())
+    }
+
+    pub async fn delete_by_user_id(user_id: Uuid, pool: &DbPool) -> Result<u64> {
+        let deleted = sqlx::query("DELETE FROM sessions WHERE user_id = $1")
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(deleted.rows_affected())
+    }
+}
@@ -0,0 +1,41 @@
+use crate::database::DbPool;
+use crate::models::user::Role;
+use anyhow::Result;
+use sqlx::types::Uuid;
+use sqlx::Done;
+
+/// The first-run setup, which makes the first super admin. Once it's completed it can't be
+/// opened again, even if there's no super admin anymore.
+pub struct Setup;
+
+impl Setup {
+    pub async fn is_completed(pool: &DbPool) -> Result<bool> {
+        let row: Option<(bool,)> = sqlx::query_as("SELECT true FROM setup")
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(row.is_some())
+    }
+
+    /// Makes the user a super admin and closes the setup. Returns false if it was completed
+    /// already, e.g. on another instance.
+    pub async fn complete(user_id: Uuid, pool: &DbPool) -> Result<bool> {
+        let mut tx = pool.begin().await?;
+        let inserted =
+            sqlx::query("INSERT INTO setup (user_id) VALUES ($1) ON CONFLICT DO NOTHING")
+                .bind(user_id)
+                .execute(&mut tx)
+                .await?;
+        if inserted.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query("UPDATE users SET role = $1, updated_at = now() WHERE id = $2")
+            .bind(Role::SuperAdmin as i16)
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+}
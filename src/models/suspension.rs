@@ -0,0 +1,75 @@
+use crate::database::DbPool;
+use anyhow::Result;
+use sqlx::types::Uuid;
+use sqlx::{Done, FromRow};
+use time::PrimitiveDateTime;
+
+/// The longest reason an admin can give, see the `reason_length` constraint.
+pub const MAX_REASON_LENGTH: usize = 500;
+
+// this struct will be used to represent database record
+#[derive(FromRow, Debug)]
+pub struct Suspension {
+    pub user_id: Uuid,
+    /// The admin who suspended the user, None if their account was deleted since.
+    pub suspended_by: Option<Uuid>,
+    pub created_at: PrimitiveDateTime,
+    /// When the suspension ends, None until the user is unsuspended.
+    pub until: Option<PrimitiveDateTime>,
+    /// Why the user was suspended, it's shown to them.
+    pub reason: Option<String>,
+}
+
+// Implementation for Suspension struct, a user is suspended while there's a record that hasn't
+// ended yet
+impl Suspension {
+    pub async fn find_by_user_id(user_id: Uuid, pool: &DbPool) -> Result<Option<Suspension>> {
+        let suspension = sqlx::query_as!(
+            Suspension,
+            "
+                SELECT * FROM suspensions
+                WHERE user_id = $1 AND (until IS NULL OR until > now())
+            ",
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(suspension)
+    }
+
+    /// Suspends the user, or replaces the end and reason of their suspension.
+    pub async fn create(
+        user_id: Uuid,
+        suspended_by: Uuid,
+        until: Option<PrimitiveDateTime>,
+        reason: Option<&str>,
+        pool: &DbPool,
+    ) -> Result<u64> {
+        let inserted = sqlx::query(
+            "
+                INSERT INTO suspensions (user_id, suspended_by, until, reason)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (user_id) DO UPDATE SET suspended_by = $2, until = $3, reason = $4,
+                created_at = now()
+            ",
+        )
+        .bind(user_id)
+        .bind(suspended_by)
+        .bind(until)
+        .bind(reason)
+        .execute(pool)
+        .await?;
+
+        Ok(inserted.rows_affected())
+    }
+
+    pub async fn delete(user_id: Uuid, pool: &DbPool) -> Result<u64> {
+        let deleted = sqlx::query("DELETE FROM suspensions WHERE user_id = $1")
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(deleted.rows_affected())
+    }
+}
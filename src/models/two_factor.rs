@@ -0,0 +1,148 @@
+use crate::database::DbPool;
+use crate::token;
+use crate::totp;
+use anyhow::Result;
+use sqlx::types::Uuid;
+use sqlx::{Done, FromRow, Postgres, Transaction};
+use time::PrimitiveDateTime;
+
+// this struct will be used to represent database record
+#[derive(FromRow)]
+pub struct TwoFactor {
+    pub user_id: Uuid,
+    /// The base32 encoded TOTP secret.
+    pub secret: String,
+    pub last_step: Option<i64>,
+    pub created_at: PrimitiveDateTime,
+}
+
+// Implementation for TwoFactor struct, functions for read/write two-factor settings and
+// recovery codes from database
+impl TwoFactor {
+    /// Returns None if the user didn't enable two-factor authentication.
+    pub async fn find_by_user_id(user_id: Uuid, pool: &DbPool) -> Result<Option<TwoFactor>> {
+        let two_factor = sqlx::query_as!(
+            TwoFactor,
+            "SELECT * FROM two_factor WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(two_factor)
+    }
+
+    /// Enables two-factor authentication with a secret the user confirmed with a code, and
+    /// returns the new recovery codes.
+    pub async fn enable(
+        user_id: Uuid,
+        secret: &str,
+        step: i64,
+        pool: &DbPool,
+    ) -> Result<Vec<String>> {
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+            "
+                INSERT INTO two_factor (user_id, secret, last_step) VALUES ($1, $2, $3)
+                ON CONFLICT (user_id) DO UPDATE SET secret = $2, last_step = $3, created_at = now()
+            ",
+        )
+        .bind(user_id)
+        .bind(secret)
+        .bind(step)
+        .execute(&mut tx)
+        .await?;
+        let codes = TwoFactor::insert_recovery_codes(user_id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(codes)
+    }
+
+    /// Accepts a time step only once, and only if it's newer than the last one.
+    pub async fn use_step(user_id: Uuid, step: i64, pool: &DbPool) -> Result<bool> {
+        let updated = sqlx::query(
+            "
+                UPDATE two_factor SET last_step = $2
+                WHERE user_id = $1 AND (last_step IS NULL OR last_step < $2)
+            ",
+        )
+        .bind(user_id)
+        .bind(step)
+        .execute(pool)
+        .await?;
+
+        Ok(updated.rows_affected() > 0)
+    }
+
+    /// Uses up a recovery code, returns false if it doesn't exist or was used already.
+    pub async fn use_recovery_code(user_id: Uuid, code: &str, pool: &DbPool) -> Result<bool> {
+        let updated = sqlx::query(
+            "
+                UPDATE recovery_codes SET used_at = now()
+                WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+            ",
+        )
+        .bind(user_id)
+        .bind(token::hash(&totp::normalize_recovery_code(code)))
+        .execute(pool)
+        .await?;
+
+        Ok(updated.rows_affected() > 0)
+    }
+
+    pub async fn count_recovery_codes(user_id: Uuid, pool: &DbPool) -> Result<i64> {
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT count(*) FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    /// Replaces the recovery codes of the user with new ones.
+    pub async fn regenerate_recovery_codes(user_id: Uuid, pool: &DbPool) -> Result<Vec<String>> {
+        let mut tx = pool.begin().await?;
+        let codes = TwoFactor::insert_recovery_codes(user_id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(codes)
+    }
+
+    async fn insert_recovery_codes(
+        user_id: Uuid,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<Vec<String>> {
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        let codes = totp::generate_recovery_codes();
+        for code in &codes {
+            sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+                .bind(user_id)
+                .bind(token::hash(&totp::normalize_recovery_code(code)))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        Ok(codes)
+    }
+
+    /// Turns two-factor authentication off, also used by admins when a user lost their device.
+    pub async fn disable(user_id: Uuid, pool: &DbPool) -> Result<u64> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+        let deleted = sqlx::query("DELETE FROM two_factor WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(deleted.rows_affected())
+    }
+}
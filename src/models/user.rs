@@ -1,19 +1,39 @@
 use crate::database::DbPool;
 use crate::models::uuid_serializer;
-use actix_identity::Identity;
+use crate::models::TwoFactor;
 use actix_web::{Error, HttpRequest, HttpResponse, Responder};
 use anyhow::Result;
 use futures::future::{ready, Ready};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use slug::slugify;
 use sqlx::types::Uuid;
 use sqlx::{Done, FromRow};
+use std::cmp::Ordering;
 use time::PrimitiveDateTime;
 
+/// Same as GitHub, see the `username_length` constraint of the users table.
+pub const MAX_USERNAME_LENGTH: usize = 39;
+
+/// Usernames picked at registration follow GitHub's rules: letters, digits and single dashes
+/// that don't start or end the name.
+pub fn is_valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= MAX_USERNAME_LENGTH
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !username.starts_with('-')
+        && !username.ends_with('-')
+        && !username.contains("--")
+}
+
 // this struct will use to receive user input
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct UserRequest {
     pub username: String,
     pub email: Option<String>,
+    /// Only ever an argon2 hash, never read from a request. See `User::set_password`.
+    #[serde(skip)]
     pub password: Option<String>,
     pub name: Option<String>,
     pub avatar_url: Option<String>,
@@ -25,7 +45,8 @@ pub struct UserRequest {
     pub updated_at: PrimitiveDateTime,
 }
 
-#[derive(sqlx::Type, PartialEq)]
+/// Roles are ordered by privilege, so `role >= Role::Editor` means "at least an editor".
+#[derive(sqlx::Type, PartialEq, Eq, Clone, Copy, Debug)]
 #[repr(i16)]
 pub enum Role {
     /// access to everything
@@ -43,6 +64,63 @@ pub enum Role {
     Guest = 7,
 }
 
+impl Role {
+    /// Roles that can change what everyone sees have to sign in with two-factor authentication.
+    pub fn requires_two_factor(&self) -> bool {
+        matches!(self, Role::SuperAdmin | Role::Admin | Role::Editor)
+    }
+
+    /// Whether posts written by this role can be made publicly visible without review.
+    pub fn can_publish(&self) -> bool {
+        matches!(
+            self,
+            Role::SuperAdmin | Role::Admin | Role::Editor | Role::Author
+        )
+    }
+
+    /// Whether this role can approve, request changes to or reject posts submitted for review.
+    pub fn can_review(&self) -> bool {
+        matches!(self, Role::SuperAdmin | Role::Admin | Role::Editor)
+    }
+
+    /// Whether someone with this role can change, suspend or delete a user with the role
+    /// `other`. Only super admins can manage their equals.
+    pub fn can_manage(&self, other: Role) -> bool {
+        *self == Role::SuperAdmin || *self > other
+    }
+
+    /// Whether someone with this role can give `role` to a user, a role below their own unless
+    /// they're a super admin.
+    pub fn can_assign(&self, role: Role) -> bool {
+        role != Role::Guest && self.can_manage(role)
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Role::SuperAdmin => "Super admin",
+            Role::Admin => "Admin",
+            Role::Editor => "Editor",
+            Role::Author => "Author",
+            Role::Contributor => "Contributor",
+            Role::Subscriber => "Subscriber",
+            Role::Guest => "Guest",
+        }
+    }
+}
+
+// Declared from the most to the least privileged, so the discriminants are compared reversed.
+impl PartialOrd for Role {
+    fn partial_cmp(&self, other: &Role) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Role {
+    fn cmp(&self, other: &Role) -> Ordering {
+        (*other as i16).cmp(&(*self as i16))
+    }
+}
+
 impl Serialize for Role {
     fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
     where
@@ -81,7 +159,7 @@ impl<'de> Deserialize<'de> for Role {
 }
 
 // this struct will be used to represent database record
-#[derive(Serialize, Deserialize, FromRow)]
+#[derive(Serialize, Deserialize, FromRow, Clone)]
 pub struct User {
     #[serde(with = "uuid_serializer")]
     pub id: Uuid,
@@ -96,26 +174,15 @@ pub struct User {
     #[serde(skip_serializing)]
     pub github_token: Option<String>,
     pub role: Role,
+    /// Sessions remember the version they were created with, bumping it invalidates them all.
+    #[serde(skip)]
+    pub session_version: i32,
+    /// Whether the user proved they own `email`, only verified addresses get password resets.
+    pub email_verified: bool,
     pub created_at: PrimitiveDateTime,
     pub updated_at: PrimitiveDateTime,
 }
 
-pub trait ToUser {
-    fn user(&self) -> Option<User>;
-}
-
-impl ToUser for Identity {
-    fn user(&self) -> Option<User> {
-        match self.identity() {
-            None => None,
-            Some(identity) => match serde_json::from_str::<User>(&*identity) {
-                Ok(u) => Some(u),
-                Err(_) => None,
-            },
-        }
-    }
-}
-
 // implementation of Actix Responder for User struct so we can return User from action handler
 impl Responder for User {
     type Error = Error;
@@ -130,6 +197,21 @@ impl Responder for User {
     }
 }
 
+impl User {
+    /// The name shown in bylines, the username for users who didn't give one.
+    pub fn display_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .filter(|n| !n.is_empty())
+            .unwrap_or(&self.username)
+    }
+
+    /// The public page of the user, see `author_handlers`.
+    pub fn profile_url(&self) -> String {
+        format!("/author/{}", self.username)
+    }
+}
+
 // Implementation for User struct, functions for read/write/update and delete user from database
 impl User {
     pub async fn find_all(pool: &DbPool) -> Result<Vec<User>> {
@@ -138,7 +220,7 @@ impl User {
             r#"
                 SELECT id, username, email, password, name, avatar_url,
                 gravatar_id, github_id, github_token, role as "role: Role",
-                created_at, updated_at
+                session_version, email_verified, created_at, updated_at
                 FROM users
                 ORDER BY created_at
             "#,
@@ -155,7 +237,7 @@ impl User {
             r#"
                 SELECT id, username, email, password, name, avatar_url,
                 gravatar_id, github_id, github_token, role as "role: Role",
-                created_at, updated_at
+                session_version, email_verified, created_at, updated_at
                 FROM users WHERE id = $1
             "#,
             id
@@ -166,13 +248,31 @@ impl User {
         Ok(user)
     }
 
+    /// The users with the given ids, e.g. the authors of a list of posts.
+    pub async fn find_by_ids(ids: &[Uuid], pool: &DbPool) -> Result<Vec<User>> {
+        let users = sqlx::query_as!(
+            User,
+            r#"
+                SELECT id, username, email, password, name, avatar_url,
+                gravatar_id, github_id, github_token, role as "role: Role",
+                session_version, email_verified, created_at, updated_at
+                FROM users WHERE id = ANY($1)
+            "#,
+            ids
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(users)
+    }
+
     pub async fn find_by_github_id(id: i64, pool: &DbPool) -> Result<User> {
         let user = sqlx::query_as!(
             User,
             r#"
                 SELECT id, username, email, password, name, avatar_url,
                 gravatar_id, github_id, github_token, role as "role: Role",
-                created_at, updated_at
+                session_version, email_verified, created_at, updated_at
                 FROM users WHERE github_id = $1
             "#,
             id
@@ -183,6 +283,60 @@ impl User {
         Ok(user)
     }
 
+    pub async fn find_by_username(username: &str, pool: &DbPool) -> Result<User> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+                SELECT id, username, email, password, name, avatar_url,
+                gravatar_id, github_id, github_token, role as "role: Role",
+                session_version, email_verified, created_at, updated_at
+                FROM users WHERE username = $1
+            "#,
+            username
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Finds the user with a verified email address, case insensitive.
+    pub async fn find_by_verified_email(email: &str, pool: &DbPool) -> Result<User> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+                SELECT id, username, email, password, name, avatar_url,
+                gravatar_id, github_id, github_token, role as "role: Role",
+                session_version, email_verified, created_at, updated_at
+                FROM users WHERE lower(email) = lower($1) AND email_verified
+            "#,
+            email
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Turns a name from an identity provider into a valid username that isn't taken yet,
+    /// adding a number at the end if needed, e.g. `jane-doe-2`.
+    pub async fn unique_username(name: &str, pool: &DbPool) -> Result<String> {
+        let mut base: String = slugify(name)
+            .chars()
+            .take(MAX_USERNAME_LENGTH - 4)
+            .collect();
+        if base.is_empty() {
+            base = "user".to_string();
+        }
+        let mut username = base.clone();
+        let mut n = 2;
+        while User::find_by_username(&username, pool).await.is_ok() {
+            username = format!("{}-{}", base, n);
+            n += 1;
+        }
+        Ok(username)
+    }
+
     pub async fn create(user: UserRequest, pool: &DbPool) -> Result<User> {
         let user = sqlx::query_as!(
             User,
@@ -190,7 +344,7 @@ impl User {
             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
             RETURNING id, username, email, password, name, avatar_url,
                 gravatar_id, github_id, github_token, role as "role: Role",
-                created_at, updated_at
+                session_version, email_verified, created_at, updated_at
             "#,
             user.username,
             user.email,
@@ -214,16 +368,16 @@ impl User {
         let user = sqlx::query_as!(
             User,
             r#"
-                UPDATE users SET username = $1, email = $2, password = $3, name = $4, avatar_url = $5,
-                gravatar_id = $6, github_token = $7, role = $8, updated_at = now()
-                WHERE id = $9
+                UPDATE users SET username = $1, email = $2, name = $3, avatar_url = $4,
+                gravatar_id = $5, github_token = $6, role = $7, updated_at = now(),
+                email_verified = email_verified AND email IS NOT DISTINCT FROM $2
+                WHERE id = $8
                 RETURNING id, username, email, password, name, avatar_url,
                 gravatar_id, github_id, github_token, role as "role: Role",
-                created_at, updated_at
+                session_version, email_verified, created_at, updated_at
             "#,
             user.username,
             user.email,
-            user.password,
             user.name,
             user.avatar_url,
             user.gravatar_id,
@@ -231,12 +385,140 @@ impl User {
             user.role as i16,
             id,
         )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Stores what a login provider synced, like `update` but the role is left alone. The email
+    /// is no longer verified once it changes.
+    pub async fn update_profile(id: Uuid, user: &UserRequest, pool: &DbPool) -> Result<User> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+                UPDATE users SET username = $1, email = $2, name = $3, avatar_url = $4,
+                gravatar_id = $5, github_token = $6, updated_at = now(),
+                email_verified = email_verified AND email IS NOT DISTINCT FROM $2
+                WHERE id = $7
+                RETURNING id, username, email, password, name, avatar_url,
+                gravatar_id, github_id, github_token, role as "role: Role",
+                session_version, email_verified, created_at, updated_at
+            "#,
+            user.username,
+            user.email,
+            user.name,
+            user.avatar_url,
+            user.gravatar_id,
+            user.github_token,
+            id,
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Stores a new password, `hash` has to come from `password::hash`.
+    pub async fn set_password(id: Uuid, hash: &str, pool: &DbPool) -> Result<u64> {
+        let updated =
+            sqlx::query("UPDATE users SET password = $1, updated_at = now() WHERE id = $2")
+                .bind(hash)
+                .bind(id)
+                .execute(pool)
+                .await?;
+
+        Ok(updated.rows_affected())
+    }
+
+    pub async fn set_role(id: Uuid, role: Role, pool: &DbPool) -> Result<u64> {
+        let updated = sqlx::query("UPDATE users SET role = $1, updated_at = now() WHERE id = $2")
+            .bind(role as i16)
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(updated.rows_affected())
+    }
+
+    /// The name shown on the public pages of the user, None to show the username.
+    pub async fn set_name(id: Uuid, name: Option<&str>, pool: &DbPool) -> Result<u64> {
+        let updated = sqlx::query("UPDATE users SET name = $1, updated_at = now() WHERE id = $2")
+            .bind(name)
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(updated.rows_affected())
+    }
+
+    pub async fn record_login(id: Uuid, pool: &DbPool) -> Result<u64> {
+        let updated = sqlx::query("UPDATE users SET last_login_at = now() WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(updated.rows_affected())
+    }
+
+    /// Marks `email` as verified, unless the user changed their address in the meantime.
+    pub async fn verify_email(id: Uuid, email: &str, pool: &DbPool) -> Result<u64> {
+        let updated =
+            sqlx::query("UPDATE users SET email_verified = true WHERE id = $1 AND email = $2")
+                .bind(id)
+                .bind(email)
+                .execute(pool)
+                .await?;
+
+        Ok(updated.rows_affected())
+    }
+
+    /// Logs the user out of every session.
+    pub async fn bump_session_version(id: Uuid, pool: &DbPool) -> Result<u64> {
+        let updated =
+            sqlx::query("UPDATE users SET session_version = session_version + 1 WHERE id = $1")
+                .bind(id)
+                .execute(pool)
+                .await?;
+
+        Ok(updated.rows_affected())
+    }
+
+    /// Call it after giving the user `role`. If the role requires two-factor authentication and
+    /// the user hasn't set it up, they're logged out everywhere and set it up when they sign in
+    /// again. Returns whether they were logged out.
+    pub async fn require_two_factor_setup(id: Uuid, role: Role, pool: &DbPool) -> Result<bool> {
+        if !role.requires_two_factor() || TwoFactor::find_by_user_id(id, pool).await?.is_some() {
+            return Ok(false);
+        }
+        User::bump_session_version(id, pool).await?;
+
+        Ok(true)
+    }
+
+    /// The number of posts the user wrote, whatever their status.
+    pub async fn count_posts(id: Uuid, pool: &DbPool) -> Result<u64> {
+        let (count,): (i64,) = sqlx::query_as("SELECT count(*) FROM posts WHERE user_id = $1")
+            .bind(id)
             .fetch_one(pool)
             .await?;
 
-        Ok(user)
+        Ok(count as u64)
+    }
+
+    /// The number of entries in the review history of posts the user made, as an author who
+    /// submitted a post or as an editor who reviewed one.
+    pub async fn count_reviews(id: Uuid, pool: &DbPool) -> Result<u64> {
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT count(*) FROM post_reviews WHERE user_id = $1")
+                .bind(id)
+                .fetch_one(pool)
+                .await?;
+
+        Ok(count as u64)
     }
 
+    /// Fails for users who have posts or reviews, see `UserDeletion`.
     pub async fn delete(id: Uuid, pool: &DbPool) -> Result<u64> {
         let deleted = sqlx::query("DELETE FROM users WHERE id = $1")
             .bind(id)
@@ -246,3 +528,58 @@ impl User {
         Ok(deleted.rows_affected())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Role;
+
+    // from the most to the least privileged
+    const ROLES: [Role; 7] = [
+        Role::SuperAdmin,
+        Role::Admin,
+        Role::Editor,
+        Role::Author,
+        Role::Contributor,
+        Role::Subscriber,
+        Role::Guest,
+    ];
+
+    #[test]
+    fn roles_are_ordered_by_privilege() {
+        for (i, role) in ROLES.iter().enumerate() {
+            for (j, other) in ROLES.iter().enumerate() {
+                assert_eq!(role.cmp(other), j.cmp(&i), "{:?} and {:?}", role, other);
+            }
+        }
+        assert!(Role::SuperAdmin > Role::Admin);
+        assert!(Role::Editor >= Role::Editor);
+        assert!(Role::Subscriber > Role::Guest);
+    }
+
+    #[test]
+    fn roles_only_manage_lesser_roles() {
+        for (i, role) in ROLES.iter().enumerate() {
+            for (j, other) in ROLES.iter().enumerate() {
+                let manages = *role == Role::SuperAdmin || i < j;
+                assert_eq!(
+                    role.can_manage(*other),
+                    manages,
+                    "{:?} and {:?}",
+                    role,
+                    other
+                );
+                // nobody is made a guest, it's the role of visitors without an account
+                let assigns = manages && *other != Role::Guest;
+                assert_eq!(
+                    role.can_assign(*other),
+                    assigns,
+                    "{:?} and {:?}",
+                    role,
+                    other
+                );
+            }
+        }
+        assert!(!Role::Admin.can_manage(Role::Admin));
+        assert!(Role::SuperAdmin.can_assign(Role::SuperAdmin));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::database::DbPool;
+use crate::models::user::Role;
+use crate::models::User;
+use anyhow::{bail, Result};
+use sqlx::types::Uuid;
+use sqlx::Done;
+
+/// How a user is deleted. Posts and reviews reference their author, so a user who wrote any can
+/// only be deleted once they have a new author, or be anonymized instead.
+pub enum UserDeletion {
+    /// Deletes the user, fails if they have posts or reviews.
+    Delete,
+    /// Gives the posts of the user to another user and deletes the user.
+    ReassignTo(User),
+    /// Keeps the user and their posts but removes everything that identifies them and every
+    /// way to sign in.
+    Anonymize,
+}
+
+/// What happened to the user and their posts, to report back to the admin.
+pub enum DeletionOutcome {
+    Deleted,
+    Reassigned { posts: u64, to: String },
+    Anonymized { posts: u64, username: String },
+}
+
+impl DeletionOutcome {
+    /// The message shown to the admin, `username` is the one the user had.
+    pub fn message(&self, username: &str) -> String {
+        match self {
+            DeletionOutcome::Deleted => format!("@{} was deleted.", username),
+            DeletionOutcome::Reassigned { posts, to } => format!(
+                "@{} was deleted, their {} post(s) now belong to @{}.",
+                username, posts, to
+            ),
+            DeletionOutcome::Anonymized {
+                posts,
+                username: new,
+            } => format!(
+                "@{} was anonymized and renamed to @{}, their {} post(s) were kept.",
+                username, new, posts
+            ),
+        }
+    }
+}
+
+impl UserDeletion {
+    /// How many posts and reviews the user wrote, a user with any of them can't just be deleted.
+    pub async fn count_authored(id: Uuid, pool: &DbPool) -> Result<(u64, u64)> {
+        let posts = User::count_posts(id, pool).await?;
+        let reviews = User::count_reviews(id, pool).await?;
+        Ok((posts, reviews))
+    }
+
+    /// Checks that `to` can take over the posts of `user`, they have to be able to write posts.
+    pub fn reassign_to(user: &User, to: User) -> std::result::Result<UserDeletion, &'static str> {
+        if to.id == user.id {
+            return Err("The posts can't be given to the user who is deleted.");
+        }
+        if to.role < Role::Contributor {
+            return Err("The posts can only be given to someone who can write posts.");
+        }
+        Ok(UserDeletion::ReassignTo(to))
+    }
+
+    pub async fn run(self, user: &User, pool: &DbPool) -> Result<DeletionOutcome> {
+        match self {
+            UserDeletion::Delete => {
+                if UserDeletion::count_authored(user.id, pool).await? != (0, 0) {
+                    bail!("The user has posts or reviews");
+                }
+                User::delete(user.id, pool).await?;
+                Ok(DeletionOutcome::Deleted)
+            }
+            UserDeletion::ReassignTo(to) => {
+                let posts = reassign_and_delete(user.id, to.id, pool).await?;
+                Ok(DeletionOutcome::Reassigned {
+                    posts,
+                    to: to.username,
+                })
+            }
+            UserDeletion::Anonymize => {
+                let username = User::unique_username(
+                    &format!("deleted-{}", &user.id.to_simple().to_string()[..8]),
+                    pool,
+                )
+                .await?;
+                anonymize(user.id, &username, pool).await?;
+                let posts = User::count_posts(user.id, pool).await?;
+                Ok(DeletionOutcome::Anonymized { posts, username })
+            }
+        }
+    }
+}
+
+// Moves the posts, and the review history the user took part in, which can't point to a deleted
+// user either. Returns how many posts were moved.
+async fn reassign_and_delete(id: Uuid, to: Uuid, pool: &DbPool) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+    let posts = sqlx::query("UPDATE posts SET user_id = $1 WHERE user_id = $2")
+        .bind(to)
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query("UPDATE post_reviews SET user_id = $1 WHERE user_id = $2")
+        .bind(to)
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(posts.rows_affected())
+}
+
+// Scrubs the profile, the public one included, and drops the sessions, tokens, identities and
+// second factor. Without a password, provider identity or `github_id` nobody can sign in as the
+// user again, signing in with their old GitHub account creates a new user.
+async fn anonymize(id: Uuid, username: &str, pool: &DbPool) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "
+            UPDATE users SET username = $1, email = NULL, email_verified = false, password = NULL,
+            name = NULL, avatar_url = NULL, gravatar_id = NULL, github_id = NULL,
+            github_token = NULL, session_version = session_version + 1, updated_at = now()
+            WHERE id = $2
+        ",
+    )
+    .bind(username)
+    .bind(id)
+    .execute(&mut tx)
+    .await?;
+    for table in &[
+        "user_identities",
+        "user_profiles",
+        "sessions",
+        "user_tokens",
+        "api_tokens",
+        "two_factor",
+        "recovery_codes",
+        "suspensions",
+    ] {
+        sqlx::query(&format!("DELETE FROM {} WHERE user_id = $1", table))
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
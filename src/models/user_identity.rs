@@ -0,0 +1,106 @@
+use crate::database::DbPool;
+use crate::models::user::{Role, User, UserRequest};
+use crate::models::uuid_serializer;
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::types::Uuid;
+use sqlx::{Done, FromRow};
+use time::PrimitiveDateTime;
+
+// this struct will be used to represent database record
+#[derive(Serialize, FromRow)]
+pub struct UserIdentity {
+    #[serde(with = "uuid_serializer")]
+    pub id: Uuid,
+    #[serde(with = "uuid_serializer")]
+    pub user_id: Uuid,
+    /// The provider the account is at, e.g. `github` or `oidc`.
+    pub provider: String,
+    /// The id of the account at the provider, the `sub` claim for OpenID Connect.
+    pub subject: String,
+    pub created_at: PrimitiveDateTime,
+    /// The username, name, email and avatar the provider sent at the last login, see
+    /// `login::sync_profile`.
+    pub username: Option<String>,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub avatar_url: Option<String>,
+    /// None until the first login that recorded them.
+    pub synced_at: Option<PrimitiveDateTime>,
+}
+
+// Implementation for UserIdentity struct, functions for read/write identities from database
+impl UserIdentity {
+    /// Finds the user an account at a provider belongs to.
+    pub async fn find_user(provider: &str, subject: &str, pool: &DbPool) -> Result<User> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+                SELECT users.id, username, email, password, name, avatar_url,
+                gravatar_id, github_id, github_token, role as "role: Role",
+                session_version, email_verified, users.created_at, updated_at
+                FROM users
+                    JOIN user_identities ON user_identities.user_id = users.id
+                WHERE provider = $1 AND subject = $2
+            "#,
+            provider,
+            subject
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    pub async fn find(provider: &str, subject: &str, pool: &DbPool) -> Result<UserIdentity> {
+        let identity = sqlx::query_as!(
+            UserIdentity,
+            "SELECT * FROM user_identities WHERE provider = $1 AND subject = $2",
+            provider,
+            subject
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(identity)
+    }
+
+    pub async fn create(
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+        pool: &DbPool,
+    ) -> Result<UserIdentity> {
+        let identity = sqlx::query_as!(
+            UserIdentity,
+            "INSERT INTO user_identities (user_id, provider, subject) VALUES ($1, $2, $3) RETURNING *",
+            user_id,
+            provider,
+            subject,
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(identity)
+    }
+
+    /// Remembers what the provider sent, the next login compares the user with it.
+    pub async fn record_sync(id: Uuid, user: &UserRequest, pool: &DbPool) -> Result<u64> {
+        let updated = sqlx::query(
+            "
+                UPDATE user_identities SET username = $1, name = $2, email = $3, avatar_url = $4,
+                synced_at = now()
+                WHERE id = $5
+            ",
+        )
+        .bind(&user.username)
+        .bind(&user.name)
+        .bind(&user.email)
+        .bind(&user.avatar_url)
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(updated.rows_affected())
+    }
+}
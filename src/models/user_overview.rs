@@ -0,0 +1,79 @@
+use crate::database::DbPool;
+use crate::models::user::Role;
+use anyhow::Result;
+use sqlx::types::Uuid;
+use sqlx::FromRow;
+use time::PrimitiveDateTime;
+
+/// A row of the user list admins manage users with.
+#[derive(FromRow)]
+pub struct UserOverview {
+    pub id: Uuid,
+    pub username: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub role: Role,
+    pub has_password: bool,
+    pub github_id: Option<i64>,
+    /// The identity providers the user signs in with, e.g. `github`.
+    pub providers: Vec<String>,
+    pub suspended: bool,
+    pub last_login_at: Option<PrimitiveDateTime>,
+    pub created_at: PrimitiveDateTime,
+}
+
+impl UserOverview {
+    /// A page of the users whose username or email contains `search`, the newest first, and
+    /// the number of users found.
+    pub async fn search(
+        search: &str,
+        page: i64,
+        per_page: i64,
+        pool: &DbPool,
+    ) -> Result<(Vec<UserOverview>, i64)> {
+        let pattern = format!("%{}%", escape_like(search.trim()));
+        let users = sqlx::query_as::<_, UserOverview>(
+            "
+                SELECT u.id, u.username, u.name, u.email, u.role,
+                    u.password IS NOT NULL AS has_password, u.github_id,
+                    coalesce(
+                        array_agg(i.provider ORDER BY i.provider)
+                            FILTER (WHERE i.provider IS NOT NULL),
+                        '{}'::text[]
+                    ) AS providers,
+                    EXISTS (
+                        SELECT 1 FROM suspensions s
+                        WHERE s.user_id = u.id AND (s.until IS NULL OR s.until > now())
+                    ) AS suspended,
+                    u.last_login_at, u.created_at
+                FROM users u
+                LEFT JOIN user_identities i ON i.user_id = u.id
+                WHERE u.username ILIKE $1 OR u.email ILIKE $1
+                GROUP BY u.id
+                ORDER BY u.created_at DESC
+                LIMIT $2 OFFSET $3
+            ",
+        )
+        .bind(&pattern)
+        .bind(per_page)
+        .bind((page - 1).max(0) * per_page)
+        .fetch_all(pool)
+        .await?;
+
+        let (total,): (i64,) =
+            sqlx::query_as("SELECT count(*) FROM users WHERE username ILIKE $1 OR email ILIKE $1")
+                .bind(&pattern)
+                .fetch_one(pool)
+                .await?;
+
+        Ok((users, total))
+    }
+}
+
+// `%` and `_` in the search are meant literally.
+fn escape_like(search: &str) -> String {
+    search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
@@ -0,0 +1,154 @@
+use crate::database::DbPool;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use sqlx::FromRow;
+use time::PrimitiveDateTime;
+use url::Url;
+
+/// See the constraints of the user_profiles table.
+pub const MAX_BIO_LENGTH: usize = 1000;
+const MAX_URL_LENGTH: usize = 255;
+
+// this struct will use to receive user input
+#[derive(Serialize, Deserialize, Default)]
+pub struct ProfileRequest {
+    pub bio: Option<String>,
+    pub website: Option<String>,
+    /// The handle, without the `@`.
+    pub twitter: Option<String>,
+    pub github: Option<String>,
+    /// The url of the account, e.g. `https://mastodon.social/@jane`.
+    pub mastodon: Option<String>,
+}
+
+impl ProfileRequest {
+    /// Trims every field, empty ones become None and handles lose their `@`.
+    pub fn normalize(self) -> ProfileRequest {
+        let clean = |field: Option<String>| {
+            field
+                .map(|f| f.trim().to_string())
+                .filter(|f| !f.is_empty())
+        };
+        let handle =
+            |field: Option<String>| clean(field).map(|f| f.trim_start_matches('@').to_string());
+        ProfileRequest {
+            bio: clean(self.bio),
+            website: clean(self.website),
+            twitter: handle(self.twitter),
+            github: handle(self.github),
+            mastodon: clean(self.mastodon),
+        }
+    }
+
+    pub fn validate(&self) -> std::result::Result<(), &'static str> {
+        if matches!(&self.bio, Some(bio) if bio.chars().count() > MAX_BIO_LENGTH) {
+            return Err("The bio can't be longer than 1000 characters.");
+        }
+        if !self.website.as_deref().map_or(true, is_web_url) {
+            return Err("The website has to be an http or https url.");
+        }
+        if !self.mastodon.as_deref().map_or(true, is_web_url) {
+            return Err("The Mastodon account has to be an http or https url.");
+        }
+        // same rules as the services themselves
+        if !self
+            .twitter
+            .as_deref()
+            .map_or(true, |t| is_handle(t, 15, &['_']))
+        {
+            return Err("This is not a valid Twitter handle.");
+        }
+        if !self
+            .github
+            .as_deref()
+            .map_or(true, |g| is_handle(g, 39, &['-']))
+        {
+            return Err("This is not a valid GitHub username.");
+        }
+        Ok(())
+    }
+}
+
+// Links on the public page can't be `javascript:` or anything else that isn't a web page.
+fn is_web_url(url: &str) -> bool {
+    url.len() <= MAX_URL_LENGTH
+        && matches!(Url::parse(url), Ok(u) if u.scheme() == "http" || u.scheme() == "https")
+}
+
+fn is_handle(handle: &str, max_length: usize, extra: &[char]) -> bool {
+    !handle.is_empty()
+        && handle.len() <= max_length
+        && handle
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || extra.contains(&c))
+}
+
+// this struct will be used to represent database record
+#[derive(Serialize, FromRow)]
+pub struct UserProfile {
+    #[serde(skip)]
+    pub user_id: Uuid,
+    pub bio: Option<String>,
+    pub website: Option<String>,
+    pub twitter: Option<String>,
+    pub github: Option<String>,
+    pub mastodon: Option<String>,
+    pub updated_at: PrimitiveDateTime,
+}
+
+// Implementation for UserProfile struct, users without a record haven't filled in their profile
+impl UserProfile {
+    pub async fn find_by_user_id(user_id: Uuid, pool: &DbPool) -> Result<Option<UserProfile>> {
+        let profile = sqlx::query_as!(
+            UserProfile,
+            "SELECT * FROM user_profiles WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(profile)
+    }
+
+    /// Creates or replaces the profile, the request has to be normalized and valid.
+    pub async fn save(
+        user_id: Uuid,
+        profile: ProfileRequest,
+        pool: &DbPool,
+    ) -> Result<UserProfile> {
+        let profile = sqlx::query_as!(
+            UserProfile,
+            "
+                INSERT INTO user_profiles (user_id, bio, website, twitter, github, mastodon)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (user_id) DO UPDATE SET bio = $2, website = $3, twitter = $4,
+                github = $5, mastodon = $6, updated_at = now()
+                RETURNING *
+            ",
+            user_id,
+            profile.bio,
+            profile.website,
+            profile.twitter,
+            profile.github,
+            profile.mastodon,
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(profile)
+    }
+}
+
+// The saved profile, to fill in the form with.
+impl From<UserProfile> for ProfileRequest {
+    fn from(profile: UserProfile) -> ProfileRequest {
+        ProfileRequest {
+            bio: profile.bio,
+            website: profile.website,
+            twitter: profile.twitter,
+            github: profile.github,
+            mastodon: profile.mastodon,
+        }
+    }
+}
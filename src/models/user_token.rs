@@ -0,0 +1,109 @@
+use crate::database::DbPool;
+use crate::token;
+use anyhow::Result;
+use sqlx::types::Uuid;
+use time::Duration;
+
+/// What a token sent by email can be used for.
+#[derive(sqlx::Type, PartialEq, Eq, Clone, Copy, Debug)]
+#[repr(i16)]
+pub enum TokenPurpose {
+    PasswordReset = 1,
+    EmailVerification = 2,
+}
+
+impl TokenPurpose {
+    /// How long the link in the email works.
+    pub fn lifetime(&self) -> Duration {
+        match self {
+            TokenPurpose::PasswordReset => Duration::hours(1),
+            TokenPurpose::EmailVerification => Duration::days(2),
+        }
+    }
+}
+
+/// A token that was used, see `UserToken::consume`.
+pub struct UserToken {
+    pub user_id: Uuid,
+    /// The address the token was sent to.
+    pub email: Option<String>,
+}
+
+// Implementation for UserToken struct, the tokens themselves are never stored
+impl UserToken {
+    /// Creates a token and returns it to be put into the link, tokens the user got earlier for
+    /// the same purpose stop working.
+    pub async fn create(
+        user_id: Uuid,
+        purpose: TokenPurpose,
+        email: Option<&str>,
+        pool: &DbPool,
+    ) -> Result<String> {
+        let token = token::generate();
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM user_tokens WHERE user_id = $1 AND purpose = $2")
+            .bind(user_id)
+            .bind(purpose as i16)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query(
+            "
+                INSERT INTO user_tokens (user_id, purpose, token_hash, email, expires_at)
+                VALUES ($1, $2, $3, $4, now() + $5 * interval '1 second')
+            ",
+        )
+        .bind(user_id)
+        .bind(purpose as i16)
+        .bind(token::hash(&token))
+        .bind(email)
+        .bind(purpose.lifetime().as_seconds_f64())
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(token)
+    }
+
+    /// Marks the token as used, returns None if it doesn't exist, expired or was used already.
+    pub async fn consume(
+        token: &str,
+        purpose: TokenPurpose,
+        pool: &DbPool,
+    ) -> Result<Option<UserToken>> {
+        let token = sqlx::query_as!(
+            UserToken,
+            "
+                UPDATE user_tokens SET used_at = now()
+                WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > now()
+                RETURNING user_id, email
+            ",
+            token::hash(token),
+            purpose as i16,
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// The user a token that can still be used belongs to, to show the form before the token
+    /// is consumed.
+    pub async fn find_user_id(
+        token: &str,
+        purpose: TokenPurpose,
+        pool: &DbPool,
+    ) -> Result<Option<Uuid>> {
+        let user_id: Option<(Uuid,)> = sqlx::query_as(
+            "
+                SELECT user_id FROM user_tokens
+                WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > now()
+            ",
+        )
+        .bind(token::hash(token))
+        .bind(purpose as i16)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(user_id.map(|(id,)| id))
+    }
+}
@@ -0,0 +1,128 @@
+use actix_web::web;
+use anyhow::{anyhow, Result};
+use argon2::{Config, Variant};
+
+pub const MIN_PASSWORD_LENGTH: usize = 10;
+/// Hashing gets slow with very long passwords, nobody types more than this anyway.
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// A few of the most used passwords that are long enough to pass the length check.
+const COMMON_PASSWORDS: &[&str] = &[
+    "1234567890",
+    "0123456789",
+    "1q2w3e4r5t",
+    "qwertyuiop",
+    "password12",
+    "password123",
+    "password1234",
+    "iloveyou12",
+    "letmein123",
+    "welcome123",
+    "minipress123",
+];
+
+// Argon2id with the parameters recommended by OWASP.
+fn config<'a>() -> Config<'a> {
+    Config {
+        variant: Variant::Argon2id,
+        mem_cost: 19 * 1024,
+        time_cost: 2,
+        lanes: 1,
+        hash_length: 32,
+        ..Config::default()
+    }
+}
+
+/// Hashes a password into the PHC string format stored in `users.password`. Hashing takes a
+/// while on purpose, so it's done on the thread pool instead of blocking the worker.
+pub async fn hash(password: String) -> Result<String> {
+    web::block(move || {
+        let salt: [u8; 16] = rand::random();
+        argon2::hash_encoded(password.as_bytes(), &salt, &config())
+    })
+    .await
+    .map_err(|e| anyhow!("Failed to hash password: {:?}", e))
+}
+
+/// Checks a password against the stored hash. Users without a password still get a hash
+/// computed, so the response time doesn't tell whether an account has one.
+pub async fn verify(hash: Option<String>, password: String) -> bool {
+    match hash {
+        Some(hash) => web::block(move || argon2::verify_encoded(&hash, password.as_bytes()))
+            .await
+            .unwrap_or(false),
+        None => {
+            let _ = self::hash(password).await;
+            false
+        }
+    }
+}
+
+/// The rules new passwords have to follow.
+pub fn check_strength(password: &str, username: &str) -> std::result::Result<(), &'static str> {
+    let length = password.chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        return Err("The password has to be at least 10 characters long");
+    }
+    if length > MAX_PASSWORD_LENGTH {
+        return Err("The password can't be longer than 128 characters");
+    }
+    let lowercase = password.to_lowercase();
+    if !username.is_empty() && lowercase.contains(&username.to_lowercase()) {
+        return Err("The password can't contain the username");
+    }
+    let mut chars: Vec<char> = lowercase.chars().collect();
+    chars.sort_unstable();
+    chars.dedup();
+    if chars.len() < 5 || COMMON_PASSWORDS.contains(&lowercase.as_str()) {
+        return Err("The password is too easy to guess");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_need_a_reasonable_length() {
+        assert!(check_strength("x7!kq2_vbn", "alice").is_ok());
+        assert!(check_strength("x7!kq2_vb", "alice").is_err());
+        // counted in characters, not bytes
+        assert!(check_strength("ünïcødé_pä", "alice").is_ok());
+        assert!(check_strength(&"x7!kq2_vbn".repeat(12), "alice").is_ok());
+        assert!(check_strength(&"x7!kq2_vbn".repeat(13), "alice").is_err());
+    }
+
+    #[test]
+    fn passwords_cant_contain_the_username() {
+        assert!(check_strength("my-Alice-pw-7", "alice").is_err());
+        assert!(check_strength("my-Alice-pw-7", "bob").is_ok());
+        assert!(check_strength("my-Alice-pw-7", "").is_ok());
+    }
+
+    #[test]
+    fn passwords_need_some_variety() {
+        assert!(check_strength("abababababab", "alice").is_err());
+        assert!(check_strength("abcdabcdabcd", "alice").is_err());
+        assert!(check_strength("abcdeabcdeab", "alice").is_ok());
+    }
+
+    #[test]
+    fn common_passwords_are_refused() {
+        assert!(check_strength("password123", "alice").is_err());
+        assert!(check_strength("QWERTYUIOP", "alice").is_err());
+        assert!(check_strength("password1235", "alice").is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn hashes_verify_only_the_same_password() {
+        let hash = hash("correct horse".to_string()).await.unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify(Some(hash.clone()), "correct horse".to_string()).await);
+        assert!(!verify(Some(hash.clone()), "correct horse!".to_string()).await);
+        assert!(!verify(None, "correct horse".to_string()).await);
+        // a new salt every time
+        assert_ne!(hash, self::hash("correct horse".to_string()).await.unwrap());
+    }
+}
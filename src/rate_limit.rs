@@ -0,0 +1,305 @@
+use crate::database::DbPool;
+use crate::models::RateLimit;
+use actix_web::http::header;
+use actix_web::HttpResponse;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What the rate limiter counts failures for. Ip addresses get more attempts than accounts,
+/// several people can share one.
+pub enum Key {
+    Ip(String),
+    /// e.g. `user:{id}`, `login:{username}` or `email:{address}`.
+    Account(String),
+}
+
+impl Key {
+    fn name(&self) -> String {
+        match self {
+            Key::Ip(ip) => format!("ip:{}", ip),
+            Key::Account(account) => account.to_string(),
+        }
+    }
+}
+
+/// How many failures are free and after how many the key is locked.
+struct Limits {
+    attempts: u32,
+    lockout_after: u32,
+}
+
+impl Limits {
+    fn read(prefix: &str, attempts: u32, lockout_after: u32) -> Limits {
+        Limits {
+            attempts: env_number(&format!("{}_ATTEMPTS", prefix), attempts),
+            lockout_after: env_number(&format!("{}_LOCKOUT_AFTER", prefix), lockout_after),
+        }
+    }
+}
+
+enum Store {
+    /// Failures and the time of the last one, per key. Only this instance knows about them.
+    Memory(Mutex<HashMap<String, (u32, Instant)>>),
+    Postgres(DbPool),
+}
+
+/// Slows down guessing of passwords, two-factor codes and reset links. After the free
+/// attempts every failure doubles the wait before the next attempt, up to `max_delay`, and
+/// after `lockout_after` failures the key is locked for `lockout`. Failures are forgotten
+/// `window` after the last one.
+pub struct RateLimiter {
+    /// None when rate limiting is disabled.
+    store: Option<Store>,
+    ip: Limits,
+    account: Limits,
+    base_delay: Duration,
+    max_delay: Duration,
+    lockout: Duration,
+    window: Duration,
+}
+
+impl RateLimiter {
+    pub fn from_env(pool: DbPool) -> RateLimiter {
+        let store = match dotenv::var("RATE_LIMIT_STORE").as_deref() {
+            Ok("none") => {
+                log::warn!("RATE_LIMIT_STORE is none, logins are not rate limited");
+                None
+            }
+            Ok("postgres") => Some(Store::Postgres(pool)),
+            _ => Some(Store::Memory(Mutex::new(HashMap::new()))),
+        };
+        let lockout = Duration::from_secs(env_number("RATE_LIMIT_LOCKOUT", 15 * 60).into());
+        RateLimiter {
+            store,
+            ip: Limits::read("RATE_LIMIT_IP", 20, 100),
+            account: Limits::read("RATE_LIMIT_ACCOUNT", 5, 20),
+            base_delay: Duration::from_secs(env_number("RATE_LIMIT_BASE_DELAY", 1).into()),
+            max_delay: Duration::from_secs(env_number("RATE_LIMIT_MAX_DELAY", 5 * 60).into()),
+            lockout,
+            // a lockout can't end after its failures are forgotten
+            window: Duration::from_secs(env_number("RATE_LIMIT_WINDOW", 60 * 60).into())
+                .max(lockout),
+        }
+    }
+
+    /// How long to wait before the next attempt, None if it can be made right away. Errors of
+    /// the store are logged and let the attempt through.
+    pub async fn check(&self, key: &Key) -> Option<Duration> {
+        let store = self.store.as_ref()?;
+        let key_str = key.name();
+        let (failures, elapsed) = match store {
+            Store::Memory(entries) => {
+                let entries = entries.lock().unwrap();
+                let (failures, last_failure) = entries.get(&key_str)?;
+                (*failures, last_failure.elapsed())
+            }
+            Store::Postgres(pool) => match RateLimit::find(&key_str, pool).await {
+                Ok(limit) => {
+                    let limit = limit?;
+                    (limit.failures, limit.elapsed)
+                }
+                Err(e) => {
+                    log::error!("Failed to read rate limit: {}", e);
+                    return None;
+                }
+            },
+        };
+        if elapsed >= self.window {
+            return None;
+        }
+        self.delay(key, failures)
+            .checked_sub(elapsed)
+            .filter(|wait| *wait > Duration::from_secs(0))
+    }
+
+    /// Like `check`, for every key of an attempt, and returns the longest wait.
+    pub async fn check_all(&self, keys: &[Key]) -> Option<Duration> {
+        let mut wait = None;
+        for key in keys {
+            wait = wait.max(self.check(key).await);
+        }
+        wait
+    }
+
+    /// Counts a failed attempt.
+    pub async fn fail(&self, key: &Key) {
+        let key_str = key.name();
+        match &self.store {
+            Some(Store::Memory(entries)) => {
+                let mut entries = entries.lock().unwrap();
+                // drop forgotten failures so the map doesn't grow with every address ever seen
+                let window = self.window;
+                entries.retain(|_, (_, last_failure)| last_failure.elapsed() < window);
+                let failures = entries.get(&key_str).map_or(0, |(failures, _)| *failures);
+                entries.insert(key_str, (failures + 1, Instant::now()));
+            }
+            Some(Store::Postgres(pool)) => {
+                if let Err(e) = RateLimit::record(&key_str, self.window, pool).await {
+                    log::error!("Failed to record rate limit: {}", e);
+                }
+                // now and then, so not every failure has to scan the table
+                if rand::random::<u8>() == 0 {
+                    if let Err(e) = RateLimit::delete_expired(self.window, pool).await {
+                        log::error!("Failed to delete expired rate limits: {}", e);
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Forgets the failures of a key, after the account got the attempt right. Ip addresses are
+    /// never cleared, one account that works doesn't make the other guesses less likely.
+    pub async fn clear(&self, key: &Key) {
+        if let Key::Ip(_) = key {
+            return;
+        }
+        let key_str = key.name();
+        match &self.store {
+            Some(Store::Memory(entries)) => {
+                entries.lock().unwrap().remove(&key_str);
+            }
+            Some(Store::Postgres(pool)) => {
+                if let Err(e) = RateLimit::delete(&key_str, pool).await {
+                    log::error!("Failed to clear rate limit: {}", e);
+                }
+            }
+            None => {}
+        }
+    }
+
+    // The wait after the given number of failures.
+    fn delay(&self, key: &Key, failures: u32) -> Duration {
+        let limits = match key {
+            Key::Ip(_) => &self.ip,
+            Key::Account(_) => &self.account,
+        };
+        if failures >= limits.lockout_after {
+            return self.lockout;
+        }
+        if failures < limits.attempts {
+            return Duration::from_secs(0);
+        }
+        let doublings = (failures - limits.attempts).min(31);
+        self.base_delay
+            .checked_mul(1 << doublings)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+}
+
+fn env_number(name: &str, default: u32) -> u32 {
+    match dotenv::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| panic!("Invalid {}", name)),
+        Err(_) => default,
+    }
+}
+
+/// The response when an attempt has to wait, `middleware::error_handlers` renders the page.
+pub fn too_many_requests(wait: Duration) -> HttpResponse {
+    // rounded up, a client retrying right on time shouldn't be a second early
+    let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    HttpResponse::TooManyRequests()
+        .header(header::RETRY_AFTER, seconds.to_string())
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter {
+            store: Some(Store::Memory(Mutex::new(HashMap::new()))),
+            ip: Limits {
+                attempts: 4,
+                lockout_after: 10,
+            },
+            account: Limits {
+                attempts: 2,
+                lockout_after: 6,
+            },
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            lockout: Duration::from_secs(60),
+            window: Duration::from_secs(120),
+        }
+    }
+
+    fn account() -> Key {
+        Key::Account("user:1".to_string())
+    }
+
+    fn ip() -> Key {
+        Key::Ip("127.0.0.1".to_string())
+    }
+
+    #[test]
+    fn delay_doubles_up_to_the_maximum() {
+        let limiter = limiter();
+        let delays: Vec<u64> = (0..8)
+            .map(|failures| limiter.delay(&account(), failures).as_secs())
+            .collect();
+        assert_eq!(delays, vec![0, 0, 1, 2, 4, 5, 60, 60]);
+        assert_eq!(limiter.delay(&ip(), 3), Duration::from_secs(0));
+        assert_eq!(limiter.delay(&ip(), 4), Duration::from_secs(1));
+        assert_eq!(limiter.delay(&ip(), 9), Duration::from_secs(5));
+        assert_eq!(limiter.delay(&ip(), 10), Duration::from_secs(60));
+    }
+
+    #[actix_rt::test]
+    async fn failures_after_the_free_attempts_wait() {
+        let limiter = limiter();
+        for _ in 0..2 {
+            assert_eq!(limiter.check(&account()).await, None);
+            limiter.fail(&account()).await;
+        }
+        let wait = limiter.check(&account()).await.unwrap();
+        assert!(wait <= Duration::from_secs(1));
+        // the ip address has more free attempts
+        assert_eq!(limiter.check(&ip()).await, None);
+    }
+
+    #[actix_rt::test]
+    async fn locks_out_after_too_many_failures() {
+        let limiter = limiter();
+        for _ in 0..6 {
+            limiter.fail(&account()).await;
+        }
+        let wait = limiter.check(&account()).await.unwrap();
+        assert!(wait > Duration::from_secs(55) && wait <= Duration::from_secs(60));
+        assert!(limiter.check_all(&[ip(), account()]).await.unwrap() <= wait);
+    }
+
+    #[actix_rt::test]
+    async fn forgets_failures_after_the_window() {
+        let limiter = limiter();
+        for _ in 0..6 {
+            limiter.fail(&account()).await;
+        }
+        if let Some(Store::Memory(entries)) = &limiter.store {
+            let long_ago = Instant::now() - Duration::from_secs(121);
+            entries.lock().unwrap().get_mut("user:1").unwrap().1 = long_ago;
+        }
+        assert_eq!(limiter.check(&account()).await, None);
+        // the next failure starts over
+        limiter.fail(&account()).await;
+        assert_eq!(limiter.check(&account()).await, None);
+    }
+
+    #[actix_rt::test]
+    async fn clear_only_resets_accounts() {
+        let limiter = limiter();
+        for _ in 0..6 {
+            limiter.fail(&account()).await;
+        }
+        for _ in 0..10 {
+            limiter.fail(&ip()).await;
+        }
+        limiter.clear(&account()).await;
+        limiter.clear(&ip()).await;
+        assert_eq!(limiter.check(&account()).await, None);
+        assert!(limiter.check(&ip()).await.is_some());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::models::user::Role;
+use ammonia::Builder;
+use std::borrow::Cow;
+use url::Url;
+
+// Hosts iframes may be embedded from when `SANITIZER_IFRAME_HOSTS` isn't set.
+const DEFAULT_IFRAME_HOSTS: &str = "www.youtube-nocookie.com,player.vimeo.com";
+
+// Prepended to every id so a post can't clobber globals of the page, e.g. `window.location`
+// with `<div id="location">`. Links to the headings and footnotes of the post get it too.
+const ID_PREFIX: &str = "user-content-";
+
+/// Cleans rendered html with an allow-list based on the role of the user who wrote it.
+///
+/// Everyone gets the basic markup markdown can produce. Super admins and admins may also embed
+/// iframes, as long as they point to one of the `SANITIZER_IFRAME_HOSTS` over https.
+/// Subscribers and guests only ever write comments, so they can't embed images either.
+pub fn sanitize(html: &str, role: &Role) -> String {
+    let mut builder = base_policy();
+    match role {
+        Role::SuperAdmin | Role::Admin => {
+            let hosts: Vec<String> = dotenv::var("SANITIZER_IFRAME_HOSTS")
+                .unwrap_or_else(|_| DEFAULT_IFRAME_HOSTS.to_string())
+                .split(',')
+                .map(|h| h.trim().to_lowercase())
+                .filter(|h| !h.is_empty())
+                .collect();
+            builder
+                .add_tags(&["iframe"])
+                .add_tag_attributes(
+                    "iframe",
+                    &[
+                        "src",
+                        "width",
+                        "height",
+                        "title",
+                        "allowfullscreen",
+                        "frameborder",
+                    ],
+                )
+                .attribute_filter(move |element, attribute, value| {
+                    filter_attribute(element, attribute, value, &hosts)
+                });
+        }
+        Role::Editor | Role::Author | Role::Contributor => {
+            builder.attribute_filter(|element, attribute, value| {
+                filter_attribute(element, attribute, value, &[])
+            });
+        }
+        Role::Subscriber | Role::Guest => {
+            builder
+                .rm_tags(&["img"])
+                .attribute_filter(|element, attribute, value| {
+                    filter_attribute(element, attribute, value, &[])
+                });
+        }
+    }
+    builder.clean(html).to_string()
+}
+
+// The markup pulldown-cmark generates for the extensions enabled in `markdown::render` on top
+// of ammonia's defaults.
+fn base_policy() -> Builder<'static> {
+    let mut builder = Builder::default();
+    builder
+        .id_prefix(Some(ID_PREFIX))
+        .add_tags(&["input"])
+        .add_tag_attributes("input", &["type", "checked", "disabled"])
+        .add_tag_attributes("div", &["id"])
+        .add_allowed_classes("div", &["footnote-definition"])
+        .add_allowed_classes("sup", &["footnote-reference", "footnote-definition-label"])
+        .add_tag_attributes("th", &["style"])
+        .add_tag_attributes("td", &["style"]);
+    for heading in &["h1", "h2", "h3", "h4", "h5", "h6"] {
+        builder.add_tag_attributes(heading, &["id"]);
+    }
+    builder
+}
+
+fn filter_attribute<'u>(
+    element: &str,
+    attribute: &str,
+    value: &'u str,
+    iframe_hosts: &[String],
+) -> Option<Cow<'u, str>> {
+    match (element, attribute) {
+        // only task list checkboxes
+        ("input", "type") if value != "checkbox" => None,
+        // tables only use style for column alignment
+        ("th", "style") | ("td", "style") => match value {
+            "text-align: left" | "text-align: center" | "text-align: right" => Some(value.into()),
+            _ => None,
+        },
+        ("a", "href") if value.starts_with('#') && !value[1..].starts_with(ID_PREFIX) => {
+            Some(format!("#{}{}", ID_PREFIX, &value[1..]).into())
+        }
+        ("iframe", "src") => {
+            let url = Url::parse(value).ok()?;
+            let host = url.host_str()?.to_lowercase();
+            if url.scheme() == "https" && iframe_hosts.iter().any(|h| *h == host) {
+                Some(value.into())
+            } else {
+                None
+            }
+        }
+        _ => Some(value.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitize;
+    use crate::models::user::Role;
+
+    const ALL_ROLES: [Role; 7] = [
+        Role::SuperAdmin,
+        Role::Admin,
+        Role::Editor,
+        Role::Author,
+        Role::Contributor,
+        Role::Subscriber,
+        Role::Guest,
+    ];
+
+    #[test]
+    fn removes_scripts() {
+        for role in &ALL_ROLES {
+            let html = sanitize("<p>Hi</p><script>alert(1)</script>", role);
+            assert_eq!(html, "<p>Hi</p>");
+        }
+    }
+
+    #[test]
+    fn removes_event_handlers() {
+        let html = sanitize(r#"<img src="cat.png" onerror="alert(1)">"#, &Role::Admin);
+        assert_eq!(html, r#"<img src="cat.png">"#);
+        let html = sanitize(r#"<p onclick="alert(1)">Hi</p>"#, &Role::Author);
+        assert_eq!(html, "<p>Hi</p>");
+    }
+
+    #[test]
+    fn removes_svg() {
+        for role in &ALL_ROLES {
+            let html = sanitize(
+                r#"<svg onload="alert(1)"><circle r="1"></circle></svg>"#,
+                role,
+            );
+            assert!(
+                !html.contains("svg") && !html.contains("onload"),
+                "{}",
+                html
+            );
+        }
+    }
+
+    #[test]
+    fn removes_script_and_data_urls() {
+        for role in &ALL_ROLES {
+            let html = sanitize(r#"<a href="javascript:alert(1)">x</a>"#, role);
+            assert!(!html.contains("javascript"), "{}", html);
+            let html = sanitize(
+                r#"<a href="data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==">x</a>"#,
+                role,
+            );
+            assert!(!html.contains("data:"), "{}", html);
+            let html = sanitize(
+                r#"<img src="data:image/svg+xml,<svg onload=alert(1)>">"#,
+                role,
+            );
+            assert!(!html.contains("data:"), "{}", html);
+        }
+    }
+
+    #[test]
+    fn only_embeds_iframes_from_allowed_hosts() {
+        let html = sanitize(
+            r#"<iframe src="https://evil.example.com/embed"></iframe>"#,
+            &Role::Admin,
+        );
+        assert!(!html.contains("evil.example.com"), "{}", html);
+        let html = sanitize(
+            r#"<iframe src="http://www.youtube-nocookie.com/embed/x"></iframe>"#,
+            &Role::SuperAdmin,
+        );
+        assert!(!html.contains("src"), "{}", html);
+        let html = sanitize(
+            r#"<iframe src="https://www.youtube-nocookie.com/embed/x"></iframe>"#,
+            &Role::Editor,
+        );
+        assert!(!html.contains("iframe"), "{}", html);
+    }
+
+    #[test]
+    fn removes_images_of_subscribers_and_guests() {
+        let img = r#"<p><img src="cat.png" alt="A cat"></p>"#;
+        assert_eq!(sanitize(img, &Role::Author), img);
+        assert_eq!(sanitize(img, &Role::Contributor), img);
+        assert_eq!(sanitize(img, &Role::Subscriber), "<p></p>");
+        assert_eq!(sanitize(img, &Role::Guest), "<p></p>");
+    }
+
+    #[test]
+    fn prefixes_ids_and_links_to_them() {
+        let html = sanitize(
+            r##"<h2 id="location">Intro</h2><div id="cookie"></div><a href="#location">Top</a>"##,
+            &Role::Author,
+        );
+        assert_eq!(
+            html,
+            r##"<h2 id="user-content-location">Intro</h2><div id="user-content-cookie"></div><a href="#user-content-location" rel="noopener noreferrer">Top</a>"##
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use crate::client_ip::client_ip;
+use crate::database::DbPool;
+use crate::middleware::render_suspended;
+use crate::models::uuid_serializer;
+use crate::models::{ApiToken, Scope, Session, Suspension, User};
+use actix_identity::Identity;
+use actix_web::dev::Payload;
+use actix_web::http::header::{AUTHORIZATION, USER_AGENT};
+use actix_web::http::{HeaderMap, Method};
+use actix_web::{web, Error, FromRequest, HttpRequest, HttpResponse};
+use anyhow::Result;
+use futures::future::LocalBoxFuture;
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a user or session loaded from the database is reused before it is loaded again.
+/// Role changes, deletions and sessions revoked on another instance take at most this long to
+/// take effect. It's also how often the last seen time of a session is updated.
+const CACHE_TTL: Duration = Duration::from_secs(10);
+
+pub type LoggedUser = User;
+
+/// What is stored in the identity cookie. The session and the user are loaded from the
+/// database on every request so changes to them take effect right away.
+#[derive(Serialize, Deserialize)]
+pub struct SessionIdentity {
+    #[serde(with = "uuid_serializer")]
+    pub session_id: Uuid,
+    #[serde(with = "uuid_serializer")]
+    pub user_id: Uuid,
+    /// Has to match `users.session_version`, bumping it logs the user out everywhere.
+    pub session_version: i32,
+}
+
+impl SessionIdentity {
+    pub fn from_identity(id: &Identity) -> Option<SessionIdentity> {
+        id.identity()
+            .and_then(|identity| SessionIdentity::parse(&identity))
+    }
+
+    /// Reads the identity cookie's value, for middlewares that don't have an `Identity`.
+    pub fn parse(identity: &str) -> Option<SessionIdentity> {
+        serde_json::from_str(identity).ok()
+    }
+
+    pub fn remember(&self, id: &Identity) {
+        id.remember(serde_json::to_string(self).unwrap());
+    }
+}
+
+/// Validates where to send the user after logging in or out. Only paths on this site are
+/// accepted, so the param can't be used to redirect to another site, anything else gives `/`.
+pub fn safe_return_to(return_to: Option<&str>) -> String {
+    match return_to {
+        Some(path)
+            if path.starts_with('/')
+                // `//host` and `/\host` are protocol relative urls to another site
+                && !path.starts_with("//")
+                && !path.chars().any(|c| c == '\\' || c.is_control()) =>
+        {
+            path.to_string()
+        }
+        _ => "/".to_string(),
+    }
+}
+
+/// Starts a new session for the user, recording the browser and ip it was started from.
+pub async fn login(req: &HttpRequest, id: &Identity, user: &User, pool: &DbPool) -> Result<()> {
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .map(|ua| ua.chars().take(512).collect());
+    let ip =
+        client_ip(&req.connection_info(), req.peer_addr()).map(|ip| ip.chars().take(64).collect());
+    let session = Session::create(user.id, user_agent, ip, pool).await?;
+    User::record_login(user.id, pool).await?;
+    SessionIdentity {
+        session_id: session.id,
+        user_id: user.id,
+        session_version: user.session_version,
+    }
+    .remember(id);
+    Ok(())
+}
+
+/// Ends the current session, both the cookie and the database record.
+pub async fn logout(id: &Identity, pool: &DbPool, cache: &SessionCache) -> Result<()> {
+    let session = SessionIdentity::from_identity(id);
+    id.forget();
+    if let Some(session) = session {
+        cache.remove_session(session.session_id);
+        Session::delete(session.session_id, session.user_id, pool).await?;
+    }
+    Ok(())
+}
+
+/// A short lived in-process cache of logged in users and recently seen sessions, so a page with
+/// several extractors or a burst of requests doesn't hit the database every time.
+#[derive(Default)]
+pub struct SessionCache {
+    users: Mutex<HashMap<Uuid, (Instant, User)>>,
+    /// The user of each session and when it was last seen.
+    sessions: Mutex<HashMap<Uuid, (Uuid, Instant)>>,
+}
+
+impl SessionCache {
+    pub fn get(&self, id: Uuid) -> Option<User> {
+        let users = self.users.lock().unwrap();
+        match users.get(&id) {
+            Some((loaded_at, user)) if loaded_at.elapsed() < CACHE_TTL => Some(user.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, user: User) {
+        let mut users = self.users.lock().unwrap();
+        // drop expired entries so the cache doesn't grow with every user that ever logged in
+        users.retain(|_, (loaded_at, _)| loaded_at.elapsed() < CACHE_TTL);
+        users.insert(user.id, (Instant::now(), user));
+    }
+
+    /// Forgets a user, call it after changing a user so this instance picks it up right away.
+    pub fn remove(&self, id: Uuid) {
+        self.users.lock().unwrap().remove(&id);
+    }
+
+    fn session_seen_recently(&self, id: Uuid) -> bool {
+        let sessions = self.sessions.lock().unwrap();
+        matches!(sessions.get(&id), Some((_, seen_at)) if seen_at.elapsed() < CACHE_TTL)
+    }
+
+    fn session_seen(&self, id: Uuid, user_id: Uuid) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, (_, seen_at)| seen_at.elapsed() < CACHE_TTL);
+        sessions.insert(id, (user_id, Instant::now()));
+    }
+
+    /// Forgets a session, call it after revoking a session.
+    pub fn remove_session(&self, id: Uuid) {
+        self.sessions.lock().unwrap().remove(&id);
+    }
+
+    /// Forgets a user along with all of their sessions.
+    pub fn remove_user_sessions(&self, user_id: Uuid) {
+        self.remove(user_id);
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, (session_user_id, _)| *session_user_id != user_id);
+    }
+}
+
+impl FromRequest for LoggedUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<LoggedUser, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, pl: &mut Payload) -> Self::Future {
+        let db_pool = req.app_data::<web::Data<DbPool>>().cloned();
+        let cache = req.app_data::<web::Data<SessionCache>>().cloned();
+
+        // scripts send an api token instead of the identity cookie
+        if let Some(token) = bearer_token(req.headers()) {
+            let scope = required_scope(req);
+            return Box::pin(async move {
+                let (db_pool, cache) = match (db_pool, cache) {
+                    (Some(p), Some(c)) => (p, c),
+                    _ => return Err(unauthorized()),
+                };
+                let scope = match scope {
+                    Some(s) => s,
+                    None => return Err(forbidden("API tokens can't be used here")),
+                };
+                // unknown, expired or revoked
+                let token = match ApiToken::authenticate(&token, db_pool.get_ref()).await {
+                    Ok(Some(t)) => t,
+                    _ => return Err(unauthorized()),
+                };
+                if !token.has_scope(scope) {
+                    return Err(forbidden(&format!(
+                        "The token doesn't have the {} scope",
+                        scope.as_str()
+                    )));
+                }
+                // suspending deletes the sessions of the user, tokens are kept for when it ends
+                match Suspension::find_by_user_id(token.user_id, db_pool.get_ref()).await {
+                    Ok(None) => {}
+                    Ok(Some(_)) => return Err(forbidden("This account is suspended")),
+                    Err(_) => return Err(unauthorized()),
+                }
+                load_user(token.user_id, db_pool.get_ref(), &cache)
+                    .await
+                    .ok_or_else(unauthorized)
+            });
+        }
+
+        let session = Identity::from_request(req, pl)
+            .into_inner()
+            .ok()
+            .and_then(|id| SessionIdentity::from_identity(&id));
+
+        let req = req.clone();
+        Box::pin(async move {
+            let (session, db_pool, cache) = match (session, db_pool, cache) {
+                (Some(s), Some(p), Some(c)) => (s, p, c),
+                _ => return Err(unauthorized()),
+            };
+
+            if !cache.session_seen_recently(session.session_id) {
+                // the session was revoked, suspending a user revokes all of theirs
+                match Session::touch(session.session_id, session.user_id, db_pool.get_ref()).await {
+                    Ok(rows) if rows > 0 => cache.session_seen(session.session_id, session.user_id),
+                    _ => {
+                        return match Suspension::find_by_user_id(session.user_id, db_pool.get_ref())
+                            .await
+                        {
+                            Ok(Some(suspension)) => Err(render_suspended(&req, &suspension).into()),
+                            _ => Err(unauthorized()),
+                        }
+                    }
+                }
+            }
+
+            let user = load_user(session.user_id, db_pool.get_ref(), &cache)
+                .await
+                .ok_or_else(unauthorized)?;
+            if user.session_version != session.session_version {
+                return Err(unauthorized());
+            }
+            Ok(user)
+        })
+    }
+}
+
+// The user from the cache, or from the database. None if the user was deleted.
+async fn load_user(user_id: Uuid, pool: &DbPool, cache: &SessionCache) -> Option<User> {
+    if let Some(user) = cache.get(user_id) {
+        return Some(user);
+    }
+    let user = User::find_by_id(user_id, pool).await.ok()?;
+    cache.insert(user.clone());
+    Some(user)
+}
+
+fn unauthorized() -> Error {
+    HttpResponse::Unauthorized().body("Unauthorized").into()
+}
+
+fn forbidden(message: &str) -> Error {
+    HttpResponse::Forbidden().body(message.to_string()).into()
+}
+
+/// The token of an `Authorization: Bearer <token>` header.
+pub fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let mut parts = value.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => {
+            Some(token.trim().to_string())
+        }
+        _ => None,
+    }
+}
+
+// The scope a token needs for the route of the request. Only the json api can be used with a
+// token, pages and account settings like the tokens themselves need the identity cookie.
+fn required_scope(req: &HttpRequest) -> Option<Scope> {
+    let pattern = req.match_pattern()?;
+    let scope = match (req.method(), pattern.as_str()) {
+        (&Method::GET, "/post/{uuid}")
+        | (&Method::GET, "/posts/pending")
+        | (&Method::GET, "/post/{uuid}/reviews") => Scope::PostsRead,
+        (&Method::POST, "/post")
+        | (&Method::PUT, "/post/{uuid}")
+        | (&Method::DELETE, "/post/{uuid}")
+        | (&Method::POST, "/post/{uuid}/submit") => Scope::PostsWrite,
+        (&Method::POST, "/post/{uuid}/approve")
+        | (&Method::POST, "/post/{uuid}/request_changes")
+        | (&Method::POST, "/post/{uuid}/reject") => Scope::PostsReview,
+        (&Method::GET, "/users") | (&Method::GET, "/user/{uuid}") => Scope::UsersRead,
+        (&Method::POST, "/user")
+        | (&Method::PUT, "/user/{uuid}")
+        | (&Method::DELETE, "/user/{uuid}")
+        | (&Method::POST, "/user/{uuid}/sessions/revoke")
+        | (&Method::POST, "/user/{uuid}/2fa/reset") => Scope::UsersWrite,
+        _ => return None,
+    };
+    Some(scope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::safe_return_to;
+
+    #[test]
+    fn return_to_only_allows_paths_on_this_site() {
+        for unsafe_return_to in &[
+            "//evil.example",
+            "/\\evil.example",
+            "/foo\\bar",
+            "/\tevil.example",
+            "/posts\n/evil",
+            "https://evil.example/",
+            "evil.example",
+            "",
+        ] {
+            assert_eq!(
+                safe_return_to(Some(unsafe_return_to)),
+                "/",
+                "{:?}",
+                unsafe_return_to
+            );
+        }
+        assert_eq!(safe_return_to(None), "/");
+    }
+
+    #[test]
+    fn return_to_keeps_valid_paths() {
+        for path in &["/", "/account/2fa", "/setup/abc?x=1&y=2#top", "/author/ünï"] {
+            assert_eq!(safe_return_to(Some(path)), *path);
+        }
+    }
+}
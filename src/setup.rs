@@ -0,0 +1,112 @@
+use crate::database::DbPool;
+use crate::login::find_github_profile;
+use crate::models::user::Role;
+use crate::models::{Setup, User, UserIdentity};
+use crate::token;
+use anyhow::{anyhow, Result};
+use std::sync::Mutex;
+
+/// Unlocks `/setup/{token}` until the first super admin is set up. A new token is printed at
+/// every start while the setup isn't completed, only its hash is kept and only in memory.
+pub struct SetupToken(Mutex<Option<String>>);
+
+impl SetupToken {
+    pub async fn open(pool: &DbPool) -> SetupToken {
+        match Setup::is_completed(pool).await {
+            Ok(false) => {
+                let token = token::generate();
+                let base_url = dotenv::var("APP_BASE_URL")
+                    .unwrap_or_else(|_| "https://localhost:4343".to_string());
+                log::warn!(
+                    "There is no super admin yet. Sign in and open {}/setup/{} to become one, \
+                    or run `minipress create-admin --github-login <name>`",
+                    base_url.trim_end_matches('/'),
+                    token
+                );
+                SetupToken(Mutex::new(Some(token::hash(&token))))
+            }
+            Ok(true) => SetupToken(Mutex::new(None)),
+            Err(e) => {
+                log::error!("Failed to check whether the setup is completed: {}", e);
+                SetupToken(Mutex::new(None))
+            }
+        }
+    }
+
+    pub fn matches(&self, token: &str) -> bool {
+        match &*self.0.lock().unwrap() {
+            Some(hash) => *hash == token::hash(token),
+            None => false,
+        }
+    }
+
+    /// Called once the setup is completed, here or on another instance.
+    pub fn close(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+}
+
+/// `minipress create-admin --github-login <name> [--force]`, makes the GitHub user a super admin.
+/// If they never signed in their account is created, they sign in with GitHub as usual then.
+/// Once the setup is completed there is a super admin already, more are only made with
+/// `--force`.
+pub async fn create_admin(args: &[String], pool: &DbPool) -> Result<User> {
+    let force = args.iter().any(|arg| arg == "--force");
+    let args: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "--force")
+        .collect();
+    let login = match args.as_slice() {
+        ["--github-login", login] => login.to_string(),
+        [arg] if arg.starts_with("--github-login=") => {
+            arg.trim_start_matches("--github-login=").to_string()
+        }
+        _ => {
+            return Err(anyhow!(
+                "Usage: minipress create-admin --github-login <name> [--force]\n\
+                --force makes them a super admin even if the setup was completed already"
+            ))
+        }
+    };
+    if !force && Setup::is_completed(pool).await? {
+        return Err(already_completed(&login));
+    }
+
+    let mut profile = find_github_profile(&login)
+        .await
+        .map_err(|e| anyhow!("{}", e.message("GitHub")))?;
+    let github_id = profile.user.github_id.unwrap_or_default();
+    let existing = match UserIdentity::find_user("github", &profile.subject, pool).await {
+        Ok(user) => Ok(user),
+        // GitHub users used to be looked up by `github_id` only
+        Err(_) => User::find_by_github_id(github_id, pool).await,
+    };
+    let user = match existing {
+        Ok(user) => user,
+        Err(_) => {
+            profile.user.username = User::unique_username(&profile.user.username, pool).await?;
+            let user = User::create(profile.user, pool).await?;
+            UserIdentity::create(user.id, "github", &profile.subject, pool).await?;
+            user
+        }
+    };
+    // makes them the super admin and closes the setup page, unless it was completed already
+    if !Setup::complete(user.id, pool).await? {
+        if !force {
+            return Err(already_completed(&login));
+        }
+        User::set_role(user.id, Role::SuperAdmin, pool).await?;
+    }
+    User::require_two_factor_setup(user.id, Role::SuperAdmin, pool).await?;
+
+    Ok(user)
+}
+
+fn already_completed(login: &str) -> anyhow::Error {
+    anyhow!(
+        "The setup was completed already, there is a super admin. Run it again with --force to \
+        make {} a super admin too.",
+        login
+    )
+}
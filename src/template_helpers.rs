@@ -1,3 +1,5 @@
+use crate::markdown;
+use crate::models::user::Role;
 use handlebars::{Context, Handlebars, Helper, Output, RenderContext, RenderError};
 
 fn app_name_helper(
@@ -12,6 +14,24 @@ fn app_name_helper(
     Ok(())
 }
 
+// Renders the markdown passed as the first param, e.g. `{{{markdown post.excerpt}}}`.
+// Use triple braces so handlebars doesn't escape the resulting html. The author isn't known
+// here so the output is sanitized with the strictest policy.
+fn markdown_helper(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> Result<(), RenderError> {
+    let source = h.param(0).and_then(|v| v.value().as_str()).ok_or_else(|| {
+        RenderError::new("Param 0 with string type is required for markdown helper")
+    })?;
+    out.write(markdown::render(source, &Role::Guest).as_ref())?;
+    Ok(())
+}
+
 pub fn register_helpers(hb: &mut Handlebars) {
     hb.register_helper("app_name", Box::new(app_name_helper));
+    hb.register_helper("markdown", Box::new(markdown_helper));
 }
@@ -0,0 +1,13 @@
+use sha2::{Digest, Sha256};
+
+/// Generates a random token for links and api keys, 32 bytes encoded as url safe base64.
+pub fn generate() -> String {
+    let bytes: [u8; 32] = rand::random();
+    base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// The sha-256 hash of a token, which is what gets stored. Tokens are random, so they don't
+/// need a slow password hash.
+pub fn hash(token: &str) -> String {
+    base64::encode_config(&Sha256::digest(token.as_bytes()), base64::URL_SAFE_NO_PAD)
+}
@@ -0,0 +1,176 @@
+use hmac::{Hmac, Mac, NewMac};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use sha1::Sha1;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::form_urlencoded;
+
+/// Time based one time passwords as described in RFC 6238, the way authenticator apps use them:
+/// HMAC-SHA1, 6 digits and a new code every 30 seconds.
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+/// Codes of the previous and the next step are accepted too, clocks are never quite in sync.
+const ALLOWED_DRIFT: i64 = 1;
+
+const BASE32: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// A new random secret, base32 encoded like authenticator apps expect it.
+pub fn generate_secret() -> String {
+    let bytes: [u8; 20] = rand::random();
+    base32::encode(BASE32, &bytes)
+}
+
+/// Checks a code against the secret and returns the time step it belongs to. The caller has
+/// to make sure each step is only used once, see `TwoFactor::use_step`.
+pub fn verify(secret: &str, code: &str) -> Option<i64> {
+    let key = base32::decode(BASE32, secret)?;
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    // `parse` would take a sign too
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let now = current_step();
+    (now - ALLOWED_DRIFT..=now + ALLOWED_DRIFT).find(|&step| code_at(&key, step) == code)
+}
+
+fn current_step() -> i64 {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    (seconds / STEP_SECONDS) as i64
+}
+
+// HOTP (RFC 4226) with the time step as the counter.
+fn code_at(key: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_varkey(key).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    binary % 10u32.pow(DIGITS)
+}
+
+/// The `otpauth://` uri authenticator apps read from the QR code.
+pub fn provisioning_uri(secret: &str, username: &str, issuer: &str) -> String {
+    let label: String =
+        form_urlencoded::byte_serialize(format!("{}:{}", issuer, username).as_bytes()).collect();
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .finish();
+    format!("otpauth://totp/{}?{}", label, query)
+}
+
+/// Renders the uri as an SVG QR code, so the secret never leaves the server.
+pub fn qr_code_svg(uri: &str) -> String {
+    match QrCode::new(uri.as_bytes()) {
+        Ok(code) => code
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .dark_color(svg::Color("#000000"))
+            .light_color(svg::Color("#ffffff"))
+            .build(),
+        Err(_) => String::new(),
+    }
+}
+
+/// One time codes to sign in when the authenticator is lost, e.g. `k3m9x-q2w7p`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..10)
+        .map(|_| {
+            let bytes: [u8; 8] = rand::random();
+            let code = base32::encode(BASE32, &bytes).to_lowercase();
+            format!("{}-{}", &code[0..5], &code[5..10])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared without the dash and case insensitive.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA-1 test vectors of RFC 6238 appendix B, with the last 6 of their 8 digits.
+    #[test]
+    fn codes_match_the_rfc_test_vectors() {
+        let key = b"12345678901234567890";
+        for &(time, code) in &[
+            (59, 287_082),
+            (1_111_111_109, 81_804),
+            (1_111_111_111, 50_471),
+            (1_234_567_890, 5_924),
+            (2_000_000_000, 279_037),
+            (20_000_000_000, 353_130),
+        ] {
+            assert_eq!(
+                code_at(key, time / STEP_SECONDS as i64),
+                code,
+                "at {}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn verify_accepts_the_current_code() {
+        let secret = generate_secret();
+        let key = base32::decode(BASE32, &secret).unwrap();
+        let step = current_step();
+        let code = format!("{:06}", code_at(&key, step));
+        assert_eq!(verify(&secret, &code), Some(step));
+        assert_eq!(
+            verify(&secret, &format!("{} {}", &code[..3], &code[3..])),
+            Some(step)
+        );
+    }
+
+    #[test]
+    fn verify_rejects_malformed_codes() {
+        let secret = generate_secret();
+        let key = base32::decode(BASE32, &secret).unwrap();
+        let code = format!("{:06}", code_at(&key, current_step()));
+        for malformed in &[
+            "",
+            &code[..5],
+            &format!("{}0", code),
+            &format!("+{}", &code[1..]),
+            "12a456",
+            "１２３４５６",
+        ] {
+            assert_eq!(verify(&secret, malformed), None, "{:?}", malformed);
+        }
+        assert_eq!(verify("not base32!", &code), None);
+    }
+
+    #[test]
+    fn recovery_codes_survive_normalizing() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), 10);
+        for code in &codes {
+            assert_eq!(code.len(), 11);
+            assert_eq!(&code[5..6], "-");
+            let normalized = normalize_recovery_code(code);
+            assert_eq!(normalized, code.replace('-', ""));
+            assert_eq!(normalize_recovery_code(&code.to_uppercase()), normalized);
+            assert_eq!(normalize_recovery_code(&format!(" {} ", code)), normalized);
+        }
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), codes.len());
+    }
+}